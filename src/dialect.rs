@@ -0,0 +1,269 @@
+use std::{
+    fmt::{Display, Formatter},
+    path::Path,
+    str::FromStr,
+};
+
+use itertools::{Either, Itertools};
+
+use crate::program::{Position, Program};
+
+/// The width of the Befunge-93 playfield.
+pub const BEFUNGE93_WIDTH: isize = 80;
+/// The height of the Befunge-93 playfield.
+pub const BEFUNGE93_HEIGHT: isize = 25;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum Dialect {
+    /// Befunge-93, with its fixed 80x25 playfield.
+    #[default]
+    Befunge93,
+    /// Funge-98 (restricted to two dimensions), with an unbounded playfield.
+    Funge98,
+}
+
+impl Display for Dialect {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Dialect::Befunge93 => write!(f, "befunge93"),
+            Dialect::Funge98 => write!(f, "funge98"),
+        }
+    }
+}
+
+impl FromStr for Dialect {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().replace('-', "").as_str() {
+            "93" | "befunge93" => Ok(Dialect::Befunge93),
+            "98" | "funge98" | "befunge98" => Ok(Dialect::Funge98),
+            _ => Err(format!(
+                "Unknown dialect '{}' (expected befunge93 or funge98)",
+                s
+            )),
+        }
+    }
+}
+
+impl Dialect {
+    /// Guesses a program's dialect from its file extension,
+    /// returning `None` for extensions that don't indicate one.
+    pub fn from_extension(path: &Path) -> Option<Dialect> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "bf" | "b93" | "befunge93" => Some(Dialect::Befunge93),
+            "b98" | "befunge98" | "funge98" => Some(Dialect::Funge98),
+            _ => None,
+        }
+    }
+}
+
+/// The result of converting a program between dialects.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conversion {
+    pub source: String,
+    pub warnings: Vec<String>,
+}
+
+/// Rewrites a program's layout for the `to` dialect.
+///
+/// Converting to Befunge-93 pads every row to the full 80x25 playfield;
+/// converting to Funge-98 trims trailing whitespace from every row.
+/// Semantics are not changed: instead, a warning is produced for each cell
+/// that looks like it relies on the Befunge-93 playfield wrapping around
+/// (see `wrap_dependent_cells`), since that will behave differently under unbounded execution.
+pub fn convert(program: &Program, from: Dialect, to: Dialect) -> Conversion {
+    let mut warnings = vec![];
+
+    let (upper_left, lower_right) = program
+        .extent()
+        .unwrap_or((Position { x: 0, y: 0 }, Position { x: 0, y: 0 }));
+    let upper_left = Position {
+        x: upper_left.x.min(0),
+        y: upper_left.y.min(0),
+    };
+
+    if upper_left != (Position { x: 0, y: 0 }) {
+        warnings.push(format!(
+            "The program has cells at negative coordinates, starting at (x={}, y={})",
+            upper_left.x, upper_left.y
+        ));
+    }
+
+    let lower_right = match to {
+        Dialect::Befunge93 => {
+            if lower_right.x >= BEFUNGE93_WIDTH || lower_right.y >= BEFUNGE93_HEIGHT {
+                warnings.push(format!(
+                    "The program extends to (x={}, y={}), beyond the {}x{} Befunge-93 playfield",
+                    lower_right.x, lower_right.y, BEFUNGE93_WIDTH, BEFUNGE93_HEIGHT
+                ));
+            }
+            Position {
+                x: lower_right.x.max(BEFUNGE93_WIDTH - 1),
+                y: lower_right.y.max(BEFUNGE93_HEIGHT - 1),
+            }
+        }
+        Dialect::Funge98 => lower_right,
+    };
+
+    if from == Dialect::Befunge93 && to != Dialect::Befunge93 {
+        warnings.extend(wrap_dependent_cells(program).into_iter().map(|(p, c)| {
+            format!(
+                "'{}' at (x={}, y={}) sends the pointer off the edge of the program, which only wraps around in Befunge-93",
+                c, p.x, p.y
+            )
+        }));
+    }
+
+    let source = program
+        .view(&upper_left, &lower_right)
+        .group_by(|(p, _)| p.y)
+        .into_iter()
+        .map(|(_, row)| {
+            let row = row.map(|(_, c)| c).collect::<String>();
+            match to {
+                Dialect::Befunge93 => row,
+                Dialect::Funge98 => row.trim_end().to_string(),
+            }
+        })
+        .join("\n");
+
+    Conversion {
+        source: match to {
+            Dialect::Befunge93 => source,
+            Dialect::Funge98 => source.trim_end_matches('\n').to_string(),
+        },
+        warnings,
+    }
+}
+
+/// Finds direction-changing instructions that point off the edge of the program
+/// with nothing but blank cells in the way.
+/// A Befunge-93 program relies on the playfield wrapping around at those cells.
+///
+/// This is a heuristic: it does not consider whether the cells are reachable,
+/// and it misses pointers that run off an edge without passing a direction change.
+pub fn wrap_dependent_cells(program: &Program) -> Vec<(Position, char)> {
+    let blank_to = |pos: Position, dx: isize, dy: isize| {
+        let mut cells = if dx != 0 {
+            Either::Left(program.iter_row(pos.y))
+        } else {
+            Either::Right(program.iter_column(pos.x))
+        };
+        cells.all(|(p, c)| {
+            let ahead = (p.x - pos.x) * dx + (p.y - pos.y) * dy;
+            ahead <= 0 || c == ' '
+        })
+    };
+
+    program
+        .cells()
+        .filter(|(pos, c)| {
+            let (pos, c) = (*pos, *c);
+            match c {
+                '>' => blank_to(pos, 1, 0),
+                '<' => blank_to(pos, -1, 0),
+                'v' => blank_to(pos, 0, 1),
+                '^' => blank_to(pos, 0, -1),
+                '_' => blank_to(pos, 1, 0) || blank_to(pos, -1, 0),
+                '|' => blank_to(pos, 0, 1) || blank_to(pos, 0, -1),
+                '?' => {
+                    blank_to(pos, 1, 0)
+                        || blank_to(pos, -1, 0)
+                        || blank_to(pos, 0, 1)
+                        || blank_to(pos, 0, -1)
+                }
+                _ => false,
+            }
+        })
+        .sorted_by_key(|(p, _)| (p.y, p.x))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::Path, str::FromStr};
+
+    use crate::{
+        dialect::{convert, wrap_dependent_cells, Dialect},
+        program::{Position, Program},
+    };
+
+    type GenericResult = Result<(), Box<dyn std::error::Error>>;
+
+    #[test]
+    fn test_parse_dialect() {
+        assert_eq!(Dialect::from_str("befunge-93"), Ok(Dialect::Befunge93));
+        assert_eq!(Dialect::from_str("98"), Ok(Dialect::Funge98));
+        assert!(Dialect::from_str("brainfuck").is_err());
+    }
+
+    #[test]
+    fn test_wrap_dependent_cells() -> GenericResult {
+        let program = Program::from_str("v   \n>1.^\n<")?;
+
+        assert_eq!(
+            wrap_dependent_cells(&program),
+            vec![
+                (Position { x: 3, y: 1 }, '^'),
+                (Position { x: 0, y: 2 }, '<'),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_convert_to_befunge93_pads_the_playfield() -> GenericResult {
+        let conversion = convert(
+            &Program::from_str("1.@")?,
+            Dialect::Funge98,
+            Dialect::Befunge93,
+        );
+
+        let rows = conversion.source.lines().collect::<Vec<_>>();
+        assert_eq!(rows.len(), 25);
+        assert!(rows.iter().all(|row| row.len() == 80));
+        assert!(rows[0].starts_with("1.@ "));
+        assert!(conversion.warnings.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_convert_to_funge98_trims_and_warns() -> GenericResult {
+        let conversion = convert(
+            &Program::from_str("1.v  \n<  @")?,
+            Dialect::Befunge93,
+            Dialect::Funge98,
+        );
+
+        assert_eq!(conversion.source, "1.v\n<  @");
+        assert_eq!(conversion.warnings.len(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn dialect_from_extension() {
+        assert_eq!(
+            Dialect::from_extension(Path::new("hello.bf")),
+            Some(Dialect::Befunge93)
+        );
+        assert_eq!(
+            Dialect::from_extension(Path::new("dir/hello.B93")),
+            Some(Dialect::Befunge93)
+        );
+        assert_eq!(
+            Dialect::from_extension(Path::new("hello.b98")),
+            Some(Dialect::Funge98)
+        );
+        assert_eq!(
+            Dialect::from_extension(Path::new("hello.befunge98")),
+            Some(Dialect::Funge98)
+        );
+        assert_eq!(Dialect::from_extension(Path::new("hello.txt")), None);
+        assert_eq!(Dialect::from_extension(Path::new("hello")), None);
+    }
+}
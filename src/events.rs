@@ -0,0 +1,187 @@
+use std::{
+    io,
+    io::{Read, Write},
+    sync::mpsc::Sender,
+};
+
+use crate::{
+    execution::{ExecutionResult, ExecutionState, TerminationReason},
+    program::Position,
+};
+
+/// Something that happened while running a program with `run_with_events`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExecutionEvent {
+    /// The program wrote a byte of output.
+    OutputByte(u8),
+    /// The next instruction reads from the input, which may block until some is available.
+    InputRequested,
+    /// The instruction at this position was executed, bringing the count to `instruction_count`.
+    Stepped {
+        instruction_count: u64,
+        position: Position,
+    },
+    /// The program terminated, for this reason.
+    Terminated(Option<TerminationReason>),
+}
+
+/// An output that sends each byte written to it as an `ExecutionEvent::OutputByte`.
+pub struct EventOutput {
+    events: Sender<ExecutionEvent>,
+}
+
+impl EventOutput {
+    pub fn new(events: Sender<ExecutionEvent>) -> Self {
+        EventOutput { events }
+    }
+}
+
+impl Write for EventOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for byte in buf {
+            self.events
+                .send(ExecutionEvent::OutputByte(*byte))
+                .map_err(|e| io::Error::new(io::ErrorKind::BrokenPipe, e))?;
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Runs the program to termination, sending an event to `events` for each step,
+/// so that a UI can follow along from another thread.
+///
+/// Output is only sent as events if the execution writes to an `EventOutput`
+/// (which can share the same channel).
+/// An `ExecutionState` can't be sent between threads, so create it on the thread that runs it.
+/// Execution stops early, without an error, if the receiver hangs up.
+pub fn run_with_events<R: Read, O: Write>(
+    execution: &mut ExecutionState<R, O>,
+    events: Sender<ExecutionEvent>,
+) -> ExecutionResult {
+    while !execution.terminated {
+        if execution.wants_input() && events.send(ExecutionEvent::InputRequested).is_err() {
+            return Ok(());
+        }
+
+        let position = execution.pointer.position;
+        execution.step()?;
+
+        let stepped = ExecutionEvent::Stepped {
+            instruction_count: execution.instruction_count,
+            position,
+        };
+        if events.send(stepped).is_err() {
+            return Ok(());
+        }
+    }
+
+    // the receiver hanging up doesn't matter now
+    let _ = events.send(ExecutionEvent::Terminated(execution.termination));
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{str::FromStr, sync::mpsc, thread};
+
+    use crate::{
+        events::{run_with_events, EventOutput, ExecutionEvent},
+        examples::{HELLO_WORLD, INPUT},
+        execution::{ExecutionState, TerminationReason},
+        program::{Position, Program},
+    };
+
+    type GenericResult = Result<(), Box<dyn std::error::Error>>;
+
+    #[test]
+    fn hello_world_events() -> GenericResult {
+        let (sender, receiver) = mpsc::channel();
+
+        let runner = thread::spawn(move || {
+            let program = Program::from_str(HELLO_WORLD).unwrap();
+            let output = EventOutput::new(sender.clone());
+            let mut execution = ExecutionState::new(program, false, std::io::empty(), output);
+            run_with_events(&mut execution, sender)
+        });
+
+        let events: Vec<ExecutionEvent> = receiver.iter().collect();
+        runner.join().unwrap()?;
+
+        let output: Vec<u8> = events
+            .iter()
+            .filter_map(|event| match event {
+                ExecutionEvent::OutputByte(byte) => Some(*byte),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(output, b"Hello, World!\n");
+
+        let steps = events
+            .iter()
+            .filter(|event| matches!(event, ExecutionEvent::Stepped { .. }))
+            .count();
+        assert_eq!(steps, 107);
+        assert_eq!(
+            events[0],
+            ExecutionEvent::Stepped {
+                instruction_count: 1,
+                position: Position { x: 0, y: 0 }
+            }
+        );
+        assert_eq!(
+            events.last(),
+            Some(&ExecutionEvent::Terminated(Some(TerminationReason::Ended)))
+        );
+        assert!(!events.contains(&ExecutionEvent::InputRequested));
+
+        Ok(())
+    }
+
+    #[test]
+    fn input_requests() -> GenericResult {
+        let (sender, receiver) = mpsc::channel();
+
+        let program = Program::from_str(INPUT)?;
+        let mut execution = ExecutionState::new(program, false, b"42\n".as_slice(), Vec::new());
+        run_with_events(&mut execution, sender)?;
+
+        let events: Vec<ExecutionEvent> = receiver.iter().collect();
+        assert_eq!(events[0], ExecutionEvent::InputRequested);
+        assert!(matches!(events[1], ExecutionEvent::Stepped { .. }));
+        // & read ahead to the newline, so ~ doesn't need more input
+        assert_eq!(
+            events
+                .iter()
+                .filter(|event| **event == ExecutionEvent::InputRequested)
+                .count(),
+            1
+        );
+        // without an EventOutput, output isn't sent as events
+        assert!(!events
+            .iter()
+            .any(|event| matches!(event, ExecutionEvent::OutputByte(_))));
+        assert_eq!(execution.output, b"42 10 ");
+
+        Ok(())
+    }
+
+    #[test]
+    fn stops_when_the_receiver_hangs_up() -> GenericResult {
+        let (sender, receiver) = mpsc::channel();
+        drop(receiver);
+
+        let program = Program::from_str("><")?;
+        let mut execution = ExecutionState::new(program, false, std::io::empty(), Vec::new());
+        run_with_events(&mut execution, sender)?;
+
+        assert_eq!(execution.instruction_count, 1);
+
+        Ok(())
+    }
+}
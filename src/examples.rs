@@ -8,16 +8,45 @@ pub const INPUT: &str = include_str!("examples/input.bf");
 pub const QUINE: &str = include_str!("examples/quine.bf");
 pub const RNG: &str = include_str!("examples/rng.bf");
 
+/// A bundled example program.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Example {
+    pub source: &'static str,
+    /// A one-line summary of what the program does.
+    pub description: &'static str,
+}
+
 lazy_static! {
-    pub static ref EXAMPLES: HashMap<&'static str, &'static str> = {
+    pub static ref EXAMPLES: HashMap<&'static str, Example> = {
         let mut m = HashMap::new();
-        m.insert("dna", DNA);
-        m.insert("eratosthenes", ERATOSTHENES);
-        m.insert("factorial", FACTORIAL);
-        m.insert("hello_world", HELLO_WORLD);
-        m.insert("input", INPUT);
-        m.insert("quine", QUINE);
-        m.insert("rng", RNG);
+        let mut add = |name, source, description| {
+            m.insert(
+                name,
+                Example {
+                    source,
+                    description,
+                },
+            );
+        };
+        add("dna", DNA, "prints random DNA sequences forever");
+        add(
+            "eratosthenes",
+            ERATOSTHENES,
+            "prints the primes below 80, using the sieve of Eratosthenes",
+        );
+        add(
+            "factorial",
+            FACTORIAL,
+            "reads a number and prints its factorial",
+        );
+        add("hello_world", HELLO_WORLD, "prints \"Hello, World!\"");
+        add(
+            "input",
+            INPUT,
+            "reads a number and a character, and prints them both as numbers",
+        );
+        add("quine", QUINE, "a program that prints its own source");
+        add("rng", RNG, "prints random digits forever");
         m
     };
 }
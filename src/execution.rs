@@ -1,21 +1,47 @@
 use std::{
+    cell::RefCell,
     cmp::Ordering,
-    convert::TryInto,
+    collections::{HashMap, VecDeque},
+    convert::{TryFrom, TryInto},
     error::Error,
     fmt::{Display, Formatter},
+    io,
     io::{Read, Write},
+    mem,
+    rc::Rc,
+    time::{Duration, Instant},
 };
 
 use rand::{
     distributions::{Distribution, Standard},
-    prelude::ThreadRng,
-    thread_rng, Rng,
+    rngs::StdRng,
+    Rng, SeedableRng,
 };
 use time::{format_description, format_description::FormatItem, OffsetDateTime};
 
-use crate::program::{Position, Program};
+use crate::{
+    dialect::{Dialect, BEFUNGE93_HEIGHT, BEFUNGE93_WIDTH},
+    program::{Position, Program},
+};
+
+/// Every character that `ExecutionState::step` recognizes as an instruction in any dialect.
+/// `valid_instructions_match_step` checks this against what `step` actually does.
+pub const VALID_INSTRUCTIONS: &[char] = &[
+    '0', '1', '2', '3', '4', '5', '6', '7', '8', '9', '+', '-', '*', '/', '%', '!', '`', '>', '<',
+    '^', 'v', '?', '_', '|', '"', ':', '\\', '$', '.', ',', '#', 'g', 'p', '&', '~', '@', ' ',
+];
+
+/// The additional instructions that `ExecutionState::step` recognizes in the Funge-98 dialect.
+pub const FUNGE98_INSTRUCTIONS: &[char] = &['r', ';', 'k', '\'', 'q', 'n', '[', ']', 'j', 'x', 't'];
+
+/// Whether `ExecutionState::step` recognizes `c` as an instruction in `dialect`.
+pub fn is_instruction(c: char, dialect: Dialect) -> bool {
+    VALID_INSTRUCTIONS.contains(&c)
+        || (dialect == Dialect::Funge98 && FUNGE98_INSTRUCTIONS.contains(&c))
+}
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum PointerDirection {
     Up,
     Down,
@@ -23,6 +49,59 @@ pub enum PointerDirection {
     Right,
 }
 
+impl PointerDirection {
+    /// The opposite direction.
+    pub fn reversed(self) -> Self {
+        match self {
+            PointerDirection::Up => PointerDirection::Down,
+            PointerDirection::Down => PointerDirection::Up,
+            PointerDirection::Left => PointerDirection::Right,
+            PointerDirection::Right => PointerDirection::Left,
+        }
+    }
+
+    /// The direction 90 degrees anticlockwise (as seen on screen).
+    pub fn turn_left(self) -> Self {
+        match self {
+            PointerDirection::Up => PointerDirection::Left,
+            PointerDirection::Down => PointerDirection::Right,
+            PointerDirection::Left => PointerDirection::Down,
+            PointerDirection::Right => PointerDirection::Up,
+        }
+    }
+
+    /// The direction 90 degrees clockwise (as seen on screen).
+    pub fn turn_right(self) -> Self {
+        match self {
+            PointerDirection::Up => PointerDirection::Right,
+            PointerDirection::Down => PointerDirection::Left,
+            PointerDirection::Left => PointerDirection::Up,
+            PointerDirection::Right => PointerDirection::Down,
+        }
+    }
+
+    /// The change in position when moving one cell in this direction.
+    pub fn delta(self) -> (isize, isize) {
+        match self {
+            PointerDirection::Up => (0, -1),
+            PointerDirection::Down => (0, 1),
+            PointerDirection::Left => (-1, 0),
+            PointerDirection::Right => (1, 0),
+        }
+    }
+
+    /// The direction that moves by `delta`, if it is one of the four unit vectors.
+    pub fn from_delta(delta: (isize, isize)) -> Option<Self> {
+        match delta {
+            (0, -1) => Some(PointerDirection::Up),
+            (0, 1) => Some(PointerDirection::Down),
+            (-1, 0) => Some(PointerDirection::Left),
+            (1, 0) => Some(PointerDirection::Right),
+            _ => None,
+        }
+    }
+}
+
 impl Distribution<PointerDirection> for Standard {
     fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> PointerDirection {
         match rng.gen_range(0..4) {
@@ -35,46 +114,127 @@ impl Distribution<PointerDirection> for Standard {
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct InstructionPointer {
     pub position: Position,
-    pub direction: PointerDirection,
+    /// The change in position each time the pointer moves,
+    /// a unit vector unless Funge-98's `x` set it to something else.
+    pub delta: (isize, isize),
 }
 
 impl InstructionPointer {
     fn new() -> Self {
+        InstructionPointer::facing(Position { x: 0, y: 0 }, PointerDirection::Right)
+    }
+
+    pub fn facing(position: Position, direction: PointerDirection) -> Self {
         InstructionPointer {
-            position: Position { x: 0, y: 0 },
-            direction: PointerDirection::Right,
+            position,
+            delta: direction.delta(),
         }
     }
+
+    /// The direction the pointer is moving in, or `None` if its delta isn't a unit vector.
+    pub fn direction(&self) -> Option<PointerDirection> {
+        PointerDirection::from_delta(self.delta)
+    }
+
+    pub fn face(&mut self, direction: PointerDirection) {
+        self.delta = direction.delta();
+    }
+
+    fn reverse(&mut self) {
+        let (dx, dy) = self.delta;
+        self.delta = (-dx, -dy);
+    }
+
+    /// Rotates the delta 90 degrees anticlockwise (as seen on screen).
+    fn turn_left(&mut self) {
+        let (dx, dy) = self.delta;
+        self.delta = (dy, -dx);
+    }
+
+    /// Rotates the delta 90 degrees clockwise (as seen on screen).
+    fn turn_right(&mut self) {
+        let (dx, dy) = self.delta;
+        self.delta = (-dy, dx);
+    }
 }
 
-#[derive(Debug, Clone)]
-pub struct Stack(Vec<isize>);
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Stack {
+    items: Vec<isize>,
+    underflows: u64,
+    peak_depth: usize,
+}
 
 impl Stack {
     fn new() -> Stack {
-        Stack(Vec::<isize>::new())
+        Stack {
+            items: Vec::<isize>::new(),
+            underflows: 0,
+            peak_depth: 0,
+        }
     }
 
-    fn push(&mut self, val: isize) {
-        self.0.push(val);
+    /// Creates a stack holding `items`, where the first item is at the bottom of the stack
+    /// and the last item is on top, matching the order of `items()`.
+    pub fn from_vec(items: Vec<isize>) -> Stack {
+        Stack {
+            peak_depth: items.len(),
+            items,
+            underflows: 0,
+        }
+    }
+
+    /// Pushes a value onto the top of the stack.
+    pub fn push(&mut self, val: isize) {
+        self.items.push(val);
+        self.peak_depth = self.peak_depth.max(self.depth());
+    }
+
+    /// Removes and returns the value on top of the stack, or 0 if the stack is empty.
+    pub fn pop(&mut self) -> isize {
+        self.items.pop().unwrap_or_else(|| {
+            self.underflows += 1;
+            0
+        })
     }
 
-    fn pop(&mut self) -> isize {
-        self.0.pop().unwrap_or(0)
+    fn clear(&mut self) {
+        self.items.clear();
     }
 
     fn join(&self, sep: &str) -> String {
-        self.0
+        self.items
             .iter()
             .map(|x| x.to_string())
             .collect::<Vec<String>>()
             .join(sep)
     }
 
+    /// The value on top of the stack, without removing it (0 if the stack is empty).
+    pub fn peek(&self) -> isize {
+        *self.items.last().unwrap_or(&0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// The number of items on the stack, without copying them like `items()` does.
+    pub fn depth(&self) -> usize {
+        self.items.len()
+    }
+
+    /// The largest number of items the stack has held at once.
+    pub fn peak_depth(&self) -> usize {
+        self.peak_depth
+    }
+
     pub fn items(&self) -> Vec<isize> {
-        self.0.clone()
+        self.items.clone()
     }
 }
 
@@ -86,6 +246,35 @@ pub enum ExecutionError {
         position: Position,
         instruction: char,
     },
+    NegativeCoordinates {
+        position: Position,
+        target: Position,
+    },
+    DivisionByZero {
+        position: Position,
+    },
+    ArithmeticOverflow {
+        position: Position,
+    },
+    /// The pointer would have moved past the largest or smallest possible coordinate.
+    PointerOverflow {
+        position: Position,
+    },
+    OutOfBounds {
+        position: Position,
+        target: Position,
+    },
+    StackUnderflow {
+        position: Position,
+        instruction: char,
+    },
+    InfiniteLoopDetected {
+        position: Position,
+    },
+    InvalidOutputChar {
+        position: Position,
+        value: isize,
+    },
 }
 
 impl Display for ExecutionError {
@@ -107,6 +296,65 @@ impl Display for ExecutionError {
                     position.x, position.y, instruction
                 )
             }
+            ExecutionError::NegativeCoordinates { position, target } => {
+                write!(
+                    f,
+                    "Instruction at (x={}, y={}) accessed negative coordinates (x={}, y={})",
+                    position.x, position.y, target.x, target.y
+                )
+            }
+            ExecutionError::DivisionByZero { position } => {
+                write!(
+                    f,
+                    "Division by zero at (x={}, y={})",
+                    position.x, position.y
+                )
+            }
+            ExecutionError::OutOfBounds { position, target } => {
+                write!(
+                    f,
+                    "Instruction at (x={}, y={}) accessed (x={}, y={}), outside the field limit",
+                    position.x, position.y, target.x, target.y
+                )
+            }
+            ExecutionError::ArithmeticOverflow { position } => {
+                write!(
+                    f,
+                    "Arithmetic overflow at (x={}, y={})",
+                    position.x, position.y
+                )
+            }
+            ExecutionError::PointerOverflow { position } => {
+                write!(
+                    f,
+                    "Instruction pointer at (x={}, y={}) moved past the edge of the coordinate space",
+                    position.x, position.y
+                )
+            }
+            ExecutionError::StackUnderflow {
+                position,
+                instruction,
+            } => {
+                write!(
+                    f,
+                    "'{}' at (x={}, y={}) popped from an empty stack",
+                    instruction, position.x, position.y
+                )
+            }
+            ExecutionError::InvalidOutputChar { position, value } => {
+                write!(
+                    f,
+                    "',' at (x={}, y={}) tried to output {}, which isn't a byte",
+                    position.x, position.y, value
+                )
+            }
+            ExecutionError::InfiniteLoopDetected { position } => {
+                write!(
+                    f,
+                    "Infinite loop detected at (x={}, y={}): the program returned to an earlier state without any input or output",
+                    position.x, position.y
+                )
+            }
         }
     }
 }
@@ -115,17 +363,468 @@ impl Error for ExecutionError {}
 
 pub type ExecutionResult = Result<(), ExecutionError>;
 
+/// Why an execution stopped running.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TerminationReason {
+    /// The program reached `@`.
+    Ended,
+    /// The program quit with `q` and this exit code.
+    Quit(i32),
+    /// `ExecutionState::run_with_limit` ran out of steps before the program terminated.
+    StepLimit,
+    /// `ExecutionState::run_with_timeout` ran out of time before the program terminated.
+    TimedOut,
+}
+
+impl Display for TerminationReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TerminationReason::Ended => write!(f, "ended"),
+            TerminationReason::Quit(code) => write!(f, "quit with exit code {}", code),
+            TerminationReason::StepLimit => write!(f, "step limit reached"),
+            TerminationReason::TimedOut => write!(f, "timed out"),
+        }
+    }
+}
+
+/// Why `ExecutionState::run_with_limit` or `ExecutionState::run_with_timeout` stopped.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RunOutcome {
+    Terminated,
+    LimitReached,
+    TimedOut,
+}
+
+/// How many steps `ExecutionState::run_with_timeout` takes between checks of the clock,
+/// which is much slower than most instructions.
+const TIMEOUT_CHECK_INTERVAL: u64 = 1024;
+
+/// How `g` and `p` treat cells at negative coordinates,
+/// which do not exist in the Befunge-93 playfield.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum NegativeCoordinates {
+    /// Access the cell like any other.
+    #[default]
+    Allow,
+    /// Access the cell at the coordinate clamped to zero instead.
+    Clamp,
+    /// Fail with `ExecutionError::NegativeCoordinates`.
+    Error,
+}
+
+/// A limit on the cells that `g` and `p` can access,
+/// so that a self-modifying program can't grow the playfield without bound.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FieldLimit {
+    width: isize,
+    height: isize,
+    out_of_bounds: OutOfBounds,
+}
+
+impl FieldLimit {
+    /// A limit to the `width` by `height` cells starting at the origin.
+    /// Fails if the field would have no cells.
+    pub fn new(
+        width: isize,
+        height: isize,
+        out_of_bounds: OutOfBounds,
+    ) -> Result<Self, EmptyFieldLimit> {
+        if width <= 0 || height <= 0 {
+            return Err(EmptyFieldLimit { width, height });
+        }
+
+        Ok(FieldLimit {
+            width,
+            height,
+            out_of_bounds,
+        })
+    }
+
+    pub fn width(&self) -> isize {
+        self.width
+    }
+
+    pub fn height(&self) -> isize {
+        self.height
+    }
+
+    pub fn out_of_bounds(&self) -> OutOfBounds {
+        self.out_of_bounds
+    }
+}
+
+/// The error returned when creating a `FieldLimit` with no cells.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct EmptyFieldLimit {
+    pub width: isize,
+    pub height: isize,
+}
+
+impl Display for EmptyFieldLimit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "A {}x{} field limit has no cells; the width and height must be positive",
+            self.width, self.height
+        )
+    }
+}
+
+impl Error for EmptyFieldLimit {}
+
+/// How `g` and `p` treat cells outside a `FieldLimit`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum OutOfBounds {
+    /// Access the cell at the coordinates wrapped around the field instead.
+    #[default]
+    Wrap,
+    /// Fail with `ExecutionError::OutOfBounds`.
+    Error,
+}
+
+/// How arithmetic instructions handle results that don't fit in an `isize`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum ArithmeticMode {
+    /// Wrap around at the boundaries of the type.
+    #[default]
+    Wrapping,
+    /// Clamp to the largest or smallest value.
+    Saturating,
+    /// Fail with `ExecutionError::ArithmeticOverflow`.
+    Checked,
+}
+
+/// How `,` outputs values that aren't a single byte (0 to 255).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum OutputCharPolicy {
+    /// Keep only the lowest byte, so -1 outputs 255 and 300 outputs 44.
+    #[default]
+    Wrap,
+    /// Fail with `ExecutionError::InvalidOutputChar`.
+    Error,
+    /// Output the Unicode codepoint, or U+FFFD if the value isn't one (as with `unicode`).
+    Unicode,
+}
+
+/// What happens when the instruction pointer moves past the edge of the playfield.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum Wrapping {
+    /// Keep going into the infinite blank plane around the program.
+    #[default]
+    Unbounded,
+    /// Re-enter from the opposite edge of the program's extent.
+    Extent,
+    /// Re-enter from the opposite edge of the fixed 80x25 Befunge-93 playfield.
+    Befunge93,
+}
+
+/// An extra instruction pointer created by Funge-98's `t`, with its own stack.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Thread {
+    pub pointer: InstructionPointer,
+    pub stack: Stack,
+    string_mode: bool,
+}
+
+/// A copy of one instruction pointer's position, direction, stack, and string mode.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct PointerState {
+    pointer: InstructionPointer,
+    stack: Vec<isize>,
+    string_mode: bool,
+}
+
+/// Loop detection with Brent's algorithm: the state is saved after 1, 2, 4, 8, ... steps,
+/// and each later state is compared to the saved one, so a loop is found by the time
+/// it has gone around about twice after the latest save.
+/// Only one state is kept in memory, and states are compared exactly (not by hash),
+/// at the cost of noticing a loop some time after it first repeats.
+#[derive(Debug, Clone)]
+struct LoopCheck {
+    /// Every pointer's state, starting with the current pointer.
+    saved: Vec<PointerState>,
+    steps_since_save: u64,
+    steps_until_save: u64,
+}
+
+/// How trace lines are written.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum TraceFormat {
+    /// A timestamped, human-readable line per step.
+    #[default]
+    Text,
+    /// A JSON object per line, with the step's `instruction_count`, `x`, `y`,
+    /// `instruction`, `direction`, and `stack`.
+    #[cfg(feature = "serde")]
+    Json,
+}
+
+/// A copy of everything about an execution that changes as it runs,
+/// except for its input and output.
+/// A byte that `&` read ahead from the input is included, so an execution can be rewound
+/// by restoring a snapshot and rewinding the input to where it was when the snapshot was taken.
+///
+/// With the `serde` feature enabled, snapshots can be serialized to save an execution
+/// and resume it later. The random number generator and profiling data are not saved.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ExecutionSnapshot {
+    pub program: Program,
+    pub pointer: InstructionPointer,
+    pub stack: Stack,
+    #[cfg_attr(feature = "serde", serde(skip, default = "StdRng::from_entropy"))]
+    rng: StdRng,
+    pub terminated: bool,
+    pub exit_code: Option<i32>,
+    pub termination: Option<TerminationReason>,
+    string_mode: bool,
+    #[cfg_attr(feature = "serde", serde(default))]
+    threads: VecDeque<Thread>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    underflows: HashMap<Position, u64>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    histogram: HashMap<char, u64>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    heatmap: HashMap<Position, u64>,
+    pub instruction_count: u64,
+    peeked_input: Option<u8>,
+}
+
+/// What `ExecutionState::undo_step` needs to undo a single step:
+/// the state before the step, apart from the program,
+/// and the cells that `p` overwrote during it.
+/// Unlike an `ExecutionSnapshot`, this doesn't grow with the size of the program.
+/// Profiling data is not rewound.
+#[derive(Debug, Clone)]
+pub struct StepUndo {
+    pointer: InstructionPointer,
+    stack: Stack,
+    rng: StdRng,
+    terminated: bool,
+    exit_code: Option<i32>,
+    termination: Option<TerminationReason>,
+    string_mode: bool,
+    threads: VecDeque<Thread>,
+    instruction_count: u64,
+    peeked_input: Option<u8>,
+    /// The cells overwritten during the step, oldest first,
+    /// with what was stored in them before (`None` if nothing was).
+    overwritten: Vec<(Position, Option<char>)>,
+}
+
+/// A callback that observes each step of an execution.
+pub type StepCallback = Box<dyn FnMut(&StepRecord)>;
+
 pub struct ExecutionState<R: Read, O: Write> {
     pub program: Program,
     pub pointer: InstructionPointer,
     pub stack: Stack,
-    rng: ThreadRng,
+    rng: StdRng,
+    /// The seed that `rng` is reset to, if the execution is deterministic.
+    seed: Option<u64>,
     pub terminated: bool,
+    /// The exit code the program terminated with, if it terminated with `q`.
+    pub exit_code: Option<i32>,
+    /// Why the execution last stopped, if it has.
+    pub termination: Option<TerminationReason>,
     string_mode: bool,
+    /// The instruction pointers other than `pointer`, in the order they will execute.
+    threads: VecDeque<Thread>,
+    /// Whether the current instruction ended its pointer, while other pointers are still running.
+    thread_ended: bool,
+    /// The cells `p` has overwritten during `step_with_undo`, with what was stored in them before.
+    overwritten: Option<Vec<(Position, Option<char>)>>,
     trace: bool,
+    /// Where trace lines are written, stderr unless replaced.
+    trace_output: Box<dyn Write>,
+    pub trace_format: TraceFormat,
+    /// Record where the program pops from an empty stack (which pushes 0 instead).
+    pub track_underflow: bool,
+    /// Fail with `ExecutionError::StackUnderflow` when popping from an empty stack,
+    /// instead of getting 0.
+    pub strict_stack: bool,
+    /// Count how many times each instruction and each cell executes.
+    pub profile: bool,
+    /// Fail with `ExecutionError::InfiniteLoopDetected` when the pointers and stacks
+    /// return to a state they were in earlier, with no `p`, `?`, input, or output in between,
+    /// since a deterministic program in that state will repeat itself forever.
+    /// A loop may run for a few more cycles before it is noticed (see `LoopCheck`).
+    pub detect_loops: bool,
+    /// The state saved for loop detection since the last instruction that makes repeating one meaningless.
+    loop_check: Option<LoopCheck>,
+    histogram: HashMap<char, u64>,
+    heatmap: HashMap<Position, u64>,
+    pub negative_coordinates: NegativeCoordinates,
+    pub field_limit: Option<FieldLimit>,
+    pub wrap: Wrapping,
+    /// Treat characters as full Unicode codepoints in `,` and string mode,
+    /// instead of truncating them to a single byte.
+    pub unicode: bool,
+    /// How `,` outputs values that aren't a byte, unless `unicode` is set.
+    pub output_char: OutputCharPolicy,
+    pub arithmetic: ArithmeticMode,
+    /// Which dialect's instruction set to accept.
+    pub dialect: Dialect,
+    /// Written after each number that `.` outputs, a space by default (as in the Befunge-93 spec).
+    pub numeric_output_separator: String,
+    underflows: HashMap<Position, u64>,
     pub instruction_count: u64,
     pub input: R,
+    /// A byte read from the input while looking for the end of an integer, to be read next.
+    peeked_input: Option<u8>,
     pub output: O,
+    on_step: Option<StepCallback>,
+}
+
+/// Configures an `ExecutionState`, with every option defaulting to what `ExecutionState::new` uses.
+#[derive(Debug, Clone)]
+pub struct ExecutionBuilder {
+    program: Program,
+    trace: bool,
+    trace_format: TraceFormat,
+    seed: Option<u64>,
+    track_underflow: bool,
+    strict_stack: bool,
+    profile: bool,
+    detect_loops: bool,
+    negative_coordinates: NegativeCoordinates,
+    field_limit: Option<FieldLimit>,
+    wrap: Wrapping,
+    unicode: bool,
+    output_char: OutputCharPolicy,
+    arithmetic: ArithmeticMode,
+    dialect: Dialect,
+    numeric_output_separator: String,
+}
+
+impl ExecutionBuilder {
+    pub fn new(program: Program) -> Self {
+        ExecutionBuilder {
+            program,
+            trace: false,
+            trace_format: TraceFormat::Text,
+            seed: None,
+            track_underflow: false,
+            strict_stack: false,
+            profile: false,
+            detect_loops: false,
+            negative_coordinates: NegativeCoordinates::Allow,
+            field_limit: None,
+            wrap: Wrapping::Unbounded,
+            unicode: false,
+            output_char: OutputCharPolicy::Wrap,
+            arithmetic: ArithmeticMode::Wrapping,
+            dialect: Dialect::Befunge93,
+            numeric_output_separator: " ".to_string(),
+        }
+    }
+
+    pub fn trace(mut self, trace: bool) -> Self {
+        self.trace = trace;
+        self
+    }
+
+    pub fn trace_format(mut self, trace_format: TraceFormat) -> Self {
+        self.trace_format = trace_format;
+        self
+    }
+
+    /// Makes `?` choose directions deterministically, as in `ExecutionState::new_seeded`.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn track_underflow(mut self, track_underflow: bool) -> Self {
+        self.track_underflow = track_underflow;
+        self
+    }
+
+    pub fn strict_stack(mut self, strict_stack: bool) -> Self {
+        self.strict_stack = strict_stack;
+        self
+    }
+
+    pub fn profile(mut self, profile: bool) -> Self {
+        self.profile = profile;
+        self
+    }
+
+    pub fn detect_loops(mut self, detect_loops: bool) -> Self {
+        self.detect_loops = detect_loops;
+        self
+    }
+
+    pub fn negative_coordinates(mut self, negative_coordinates: NegativeCoordinates) -> Self {
+        self.negative_coordinates = negative_coordinates;
+        self
+    }
+
+    pub fn field_limit(mut self, field_limit: FieldLimit) -> Self {
+        self.field_limit = Some(field_limit);
+        self
+    }
+
+    pub fn wrap(mut self, wrap: Wrapping) -> Self {
+        self.wrap = wrap;
+        self
+    }
+
+    pub fn unicode(mut self, unicode: bool) -> Self {
+        self.unicode = unicode;
+        self
+    }
+
+    pub fn output_char(mut self, output_char: OutputCharPolicy) -> Self {
+        self.output_char = output_char;
+        self
+    }
+
+    pub fn arithmetic(mut self, arithmetic: ArithmeticMode) -> Self {
+        self.arithmetic = arithmetic;
+        self
+    }
+
+    pub fn dialect(mut self, dialect: Dialect) -> Self {
+        self.dialect = dialect;
+        self
+    }
+
+    pub fn numeric_output_separator(mut self, separator: &str) -> Self {
+        self.numeric_output_separator = separator.to_string();
+        self
+    }
+
+    pub fn build<R: Read, O: Write>(self, input: R, output: O) -> ExecutionState<R, O> {
+        let mut execution = match self.seed {
+            Some(seed) => ExecutionState::new_seeded(self.program, self.trace, input, output, seed),
+            None => ExecutionState::new(self.program, self.trace, input, output),
+        };
+        execution.trace_format = self.trace_format;
+        execution.track_underflow = self.track_underflow;
+        execution.strict_stack = self.strict_stack;
+        execution.profile = self.profile;
+        execution.detect_loops = self.detect_loops;
+        execution.negative_coordinates = self.negative_coordinates;
+        execution.field_limit = self.field_limit;
+        execution.wrap = self.wrap;
+        execution.unicode = self.unicode;
+        execution.output_char = self.output_char;
+        execution.arithmetic = self.arithmetic;
+        execution.dialect = self.dialect;
+        execution.numeric_output_separator = self.numeric_output_separator;
+        execution
+    }
+}
+
+// Not generic over the input and output, which aren't known until `build`,
+// so that `ExecutionState::builder` doesn't need type annotations.
+impl ExecutionState<io::Empty, io::Sink> {
+    pub fn builder(program: Program) -> ExecutionBuilder {
+        ExecutionBuilder::new(program)
+    }
 }
 
 lazy_static! {
@@ -142,116 +841,634 @@ impl<R: Read, O: Write> ExecutionState<R, O> {
             program,
             pointer: InstructionPointer::new(),
             stack: Stack::new(),
-            rng: thread_rng(),
+            rng: StdRng::from_entropy(),
+            seed: None,
             terminated: false,
+            exit_code: None,
+            termination: None,
             string_mode: false,
+            threads: VecDeque::new(),
+            thread_ended: false,
+            overwritten: None,
             trace,
+            trace_output: Box::new(io::stderr()),
+            trace_format: TraceFormat::Text,
+            track_underflow: false,
+            strict_stack: false,
+            profile: false,
+            detect_loops: false,
+            loop_check: None,
+            histogram: HashMap::new(),
+            heatmap: HashMap::new(),
+            negative_coordinates: NegativeCoordinates::Allow,
+            field_limit: None,
+            wrap: Wrapping::Unbounded,
+            unicode: false,
+            output_char: OutputCharPolicy::Wrap,
+            arithmetic: ArithmeticMode::Wrapping,
+            dialect: Dialect::Befunge93,
+            numeric_output_separator: " ".to_string(),
+            underflows: HashMap::new(),
             instruction_count: 0,
             input,
+            peeked_input: None,
             output,
+            on_step: None,
         }
     }
 
+    /// Creates an execution whose `?` instructions choose directions deterministically,
+    /// based on `seed`. Resetting the execution re-seeds it, so reruns behave identically.
+    pub fn new_seeded(program: Program, trace: bool, input: R, output: O, seed: u64) -> Self {
+        let mut execution = ExecutionState::new(program, trace, input, output);
+        execution.rng = StdRng::seed_from_u64(seed);
+        execution.seed = Some(seed);
+        execution
+    }
+
     pub fn reset(&mut self) {
         self.pointer = InstructionPointer::new();
         self.stack = Stack::new();
-        self.rng = thread_rng();
+        self.rng = match self.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
         self.terminated = false;
+        self.exit_code = None;
+        self.termination = None;
         self.string_mode = false;
+        self.threads.clear();
+        self.underflows.clear();
+        self.histogram.clear();
+        self.heatmap.clear();
+        self.loop_check = None;
         self.instruction_count = 0;
     }
 
-    pub fn run(&mut self) -> ExecutionResult {
-        while !self.terminated {
-            self.step()?;
+    /// Captures the current state, which can be returned to later with `restore`.
+    /// The random number generator is included, so the execution will proceed identically
+    /// after restoring (as long as it reads the same input).
+    pub fn snapshot(&self) -> ExecutionSnapshot {
+        ExecutionSnapshot {
+            program: self.program.clone(),
+            pointer: self.pointer,
+            stack: self.stack.clone(),
+            rng: self.rng.clone(),
+            terminated: self.terminated,
+            exit_code: self.exit_code,
+            termination: self.termination,
+            string_mode: self.string_mode,
+            threads: self.threads.clone(),
+            underflows: self.underflows.clone(),
+            histogram: self.histogram.clone(),
+            heatmap: self.heatmap.clone(),
+            instruction_count: self.instruction_count,
+            peeked_input: self.peeked_input,
         }
+    }
 
-        Ok(())
+    /// Returns to a previously captured state. The input and output are not affected.
+    pub fn restore(&mut self, snapshot: ExecutionSnapshot) {
+        self.program = snapshot.program;
+        self.pointer = snapshot.pointer;
+        self.stack = snapshot.stack;
+        self.rng = snapshot.rng;
+        self.terminated = snapshot.terminated;
+        self.exit_code = snapshot.exit_code;
+        self.termination = snapshot.termination;
+        self.string_mode = snapshot.string_mode;
+        self.threads = snapshot.threads;
+        self.underflows = snapshot.underflows;
+        self.histogram = snapshot.histogram;
+        self.heatmap = snapshot.heatmap;
+        self.instruction_count = snapshot.instruction_count;
+        self.peeked_input = snapshot.peeked_input;
+        self.loop_check = None;
     }
 
-    fn trace(&self) {
-        eprintln!(
-            "{} [{:4}] ({:2}, {:2}) -> {} | {}",
-            OffsetDateTime::now_local()
-                .unwrap()
-                .format(&TRACE_FORMAT)
-                .unwrap(),
-            self.instruction_count,
-            self.pointer.position.x,
-            self.pointer.position.y,
-            self.program.get(&self.pointer.position),
-            self.stack.join(" ")
-        );
+    /// Executes one step, like `step`, also returning what's needed to undo it with `undo_step`.
+    pub fn step_with_undo(&mut self) -> (StepUndo, ExecutionResult) {
+        let mut undo = StepUndo {
+            pointer: self.pointer,
+            stack: self.stack.clone(),
+            rng: self.rng.clone(),
+            terminated: self.terminated,
+            exit_code: self.exit_code,
+            termination: self.termination,
+            string_mode: self.string_mode,
+            threads: self.threads.clone(),
+            instruction_count: self.instruction_count,
+            peeked_input: self.peeked_input,
+            overwritten: vec![],
+        };
+
+        self.overwritten = Some(vec![]);
+        let result = self.step();
+        undo.overwritten = self.overwritten.take().unwrap_or_default();
+
+        (undo, result)
     }
 
-    pub fn step(&mut self) -> ExecutionResult {
-        if self.trace {
-            self.trace();
+    /// Returns to the state before the step that `undo` came from.
+    /// Steps must be undone in the reverse of the order they were taken in.
+    /// The input and output are not affected.
+    pub fn undo_step(&mut self, undo: StepUndo) {
+        for (position, stored) in undo.overwritten.into_iter().rev() {
+            match stored {
+                Some(c) => self.program.set(&position, c),
+                None => self.program.remove(&position),
+            }
         }
+        self.pointer = undo.pointer;
+        self.stack = undo.stack;
+        self.rng = undo.rng;
+        self.terminated = undo.terminated;
+        self.exit_code = undo.exit_code;
+        self.termination = undo.termination;
+        self.string_mode = undo.string_mode;
+        self.threads = undo.threads;
+        self.instruction_count = undo.instruction_count;
+        self.peeked_input = undo.peeked_input;
+        self.loop_check = None;
+    }
 
-        self.instruction_count += 1;
+    /// Serializes the current state (as a snapshot) to JSON, to be resumed with `from_json`.
+    #[cfg(feature = "serde")]
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string(&self.snapshot())
+    }
 
-        // execute instruction at pointer
-        // https://esolangs.org/wiki/Befunge#Instructions
-        match self.program.get(&self.pointer.position) {
-            '"' => self.string_mode = !self.string_mode,
-            c if self.string_mode => self.stack.push(isize::from(c as u8)),
-            '^' => self.pointer.direction = PointerDirection::Up,
-            'v' => self.pointer.direction = PointerDirection::Down,
-            '>' => self.pointer.direction = PointerDirection::Right,
-            '<' => self.pointer.direction = PointerDirection::Left,
-            '?' => self.pointer.direction = self.rng.gen(),
-            '_' => {
-                // horizontal if
-                let top = self.stack.pop();
-                if top == 0 {
-                    self.pointer.direction = PointerDirection::Right;
-                } else {
-                    self.pointer.direction = PointerDirection::Left;
-                }
-            }
-            // vertical if
-            '|' => {
-                let top = self.stack.pop();
-                if top == 0 {
-                    self.pointer.direction = PointerDirection::Down;
-                } else {
-                    self.pointer.direction = PointerDirection::Up;
-                }
-            }
-            // addition
-            '+' => {
-                let a = self.stack.pop();
-                let b = self.stack.pop();
-                self.stack.push(a + b);
-            }
-            // subtraction
-            '-' => {
-                let a = self.stack.pop();
-                let b = self.stack.pop();
-                self.stack.push(b - a);
-            }
+    /// Resumes an execution saved with `to_json`, reading from `input` and writing to `output`.
+    /// Settings like the dialect are not saved, and need to be set again.
+    #[cfg(feature = "serde")]
+    pub fn from_json(json: &str, trace: bool, input: R, output: O) -> serde_json::Result<Self> {
+        let snapshot: ExecutionSnapshot = serde_json::from_str(json)?;
+        let mut execution = ExecutionState::new(snapshot.program.clone(), trace, input, output);
+        execution.restore(snapshot);
+
+        Ok(execution)
+    }
+
+    /// The number of pops from an empty stack, if `track_underflow` is enabled.
+    pub fn underflow_count(&self) -> u64 {
+        self.underflows.values().sum()
+    }
+
+    /// The number of pops from an empty stack made by the instruction at each position,
+    /// if `track_underflow` is enabled.
+    pub fn underflow_positions(&self) -> &HashMap<Position, u64> {
+        &self.underflows
+    }
+
+    /// The number of times each instruction has executed, if `profile` is enabled.
+    /// Characters pushed in string mode are not counted.
+    pub fn instruction_histogram(&self) -> &HashMap<char, u64> {
+        &self.histogram
+    }
+
+    /// The number of times the pointer has executed each cell, if `profile` is enabled.
+    pub fn position_heatmap(&self) -> &HashMap<Position, u64> {
+        &self.heatmap
+    }
+
+    /// Consumes the execution state, returning the (possibly self-modified) program,
+    /// the stack, and the input and output.
+    pub fn into_parts(self) -> (Program, Stack, R, O) {
+        (self.program, self.stack, self.input, self.output)
+    }
+
+    /// Moves the instruction pointer without executing any instructions.
+    pub fn set_position(&mut self, pos: Position) {
+        self.pointer.position = pos;
+    }
+
+    /// Points the instruction pointer in a new direction without executing any instructions.
+    pub fn set_direction(&mut self, dir: PointerDirection) {
+        self.pointer.face(dir);
+    }
+
+    /// Registers a callback to be called with the state of the machine
+    /// just before each instruction executes, replacing any previous callback.
+    pub fn set_on_step(&mut self, on_step: StepCallback) {
+        self.on_step = Some(on_step);
+    }
+
+    /// Sends trace lines to `trace_output` instead of stderr.
+    pub fn set_trace_output(&mut self, trace_output: Box<dyn Write>) {
+        self.trace_output = trace_output;
+    }
+
+    /// Flushes any trace lines buffered by the trace output.
+    /// Call this before exiting the process, which skips destructors that would otherwise flush it.
+    pub fn flush_trace(&mut self) -> ExecutionResult {
+        self.trace_output
+            .flush()
+            .map_err(|_| ExecutionError::OutputFailed)
+    }
+
+    pub fn is_terminated(&self) -> bool {
+        self.terminated
+    }
+
+    /// The position and instruction the program terminated on, if it has terminated.
+    pub fn halted_on(&self) -> Option<(Position, char)> {
+        if self.terminated {
+            let position = self.pointer.position;
+            Some((position, self.program.get(&position)))
+        } else {
+            None
+        }
+    }
+
+    pub fn in_string_mode(&self) -> bool {
+        self.string_mode
+    }
+
+    /// Whether the instruction under the pointer will read from the input
+    /// (rather than from a byte that was already read ahead).
+    pub fn wants_input(&self) -> bool {
+        !self.terminated
+            && !self.string_mode
+            && self.peeked_input.is_none()
+            && matches!(self.program.get(&self.pointer.position), '&' | '~')
+    }
+
+    pub fn run(&mut self) -> ExecutionResult {
+        self.run_with_limit(u64::MAX)?;
+
+        Ok(())
+    }
+
+    /// Runs the program until it terminates or has executed `max_steps` more instructions.
+    pub fn run_with_limit(&mut self, max_steps: u64) -> Result<RunOutcome, ExecutionError> {
+        self.step_many(max_steps)?;
+
+        if self.terminated {
+            Ok(RunOutcome::Terminated)
+        } else {
+            self.termination = Some(TerminationReason::StepLimit);
+            Ok(RunOutcome::LimitReached)
+        }
+    }
+
+    /// Runs the program until it terminates or `timeout` has passed.
+    /// The clock is only checked every so often, so this may overrun slightly,
+    /// or by more if the program blocks waiting for input.
+    pub fn run_with_timeout(&mut self, timeout: Duration) -> Result<RunOutcome, ExecutionError> {
+        let start = Instant::now();
+
+        while !self.terminated {
+            if start.elapsed() >= timeout {
+                self.termination = Some(TerminationReason::TimedOut);
+                return Ok(RunOutcome::TimedOut);
+            }
+
+            self.step_many(TIMEOUT_CHECK_INTERVAL)?;
+        }
+
+        Ok(RunOutcome::Terminated)
+    }
+
+    fn trace(&mut self) -> ExecutionResult {
+        let line = match self.trace_format {
+            TraceFormat::Text => format!(
+                "{} [{:4}] ({:2}, {:2}) -> {} | {}",
+                // the local offset can't be determined once other threads are running
+                OffsetDateTime::now_local()
+                    .unwrap_or_else(|_| OffsetDateTime::now_utc())
+                    .format(&TRACE_FORMAT)
+                    .unwrap(),
+                self.instruction_count,
+                self.pointer.position.x,
+                self.pointer.position.y,
+                self.program.get(&self.pointer.position),
+                self.stack.join(" ")
+            ),
+            #[cfg(feature = "serde")]
+            TraceFormat::Json => serde_json::json!({
+                "instruction_count": self.instruction_count,
+                "x": self.pointer.position.x,
+                "y": self.pointer.position.y,
+                "instruction": self.program.get(&self.pointer.position),
+                "direction": self.pointer.direction(),
+                "stack": self.stack.items(),
+            })
+            .to_string(),
+        };
+
+        writeln!(self.trace_output, "{}", line).map_err(|_| ExecutionError::OutputFailed)
+    }
+
+    /// Executes one instruction with each instruction pointer.
+    pub fn step(&mut self) -> ExecutionResult {
+        if self.trace {
+            self.tick::<true>()
+        } else {
+            self.tick::<false>()
+        }
+    }
+
+    /// Executes up to `n` steps, stopping early if the program terminates,
+    /// and returns how many were executed.
+    pub fn step_many(&mut self, n: u64) -> Result<u64, ExecutionError> {
+        // checking whether to trace once, rather than on every tick
+        if self.trace {
+            self.ticks::<true>(n)
+        } else {
+            self.ticks::<false>(n)
+        }
+    }
+
+    fn ticks<const TRACE: bool>(&mut self, n: u64) -> Result<u64, ExecutionError> {
+        for executed in 0..n {
+            if self.terminated {
+                return Ok(executed);
+            }
+            self.tick::<TRACE>()?;
+        }
+
+        Ok(n)
+    }
+
+    /// Executes one instruction with each instruction pointer, in turn.
+    /// Pointers created during the tick first execute in the next one.
+    fn tick<const TRACE: bool>(&mut self) -> ExecutionResult {
+        // a step limit or timeout only describes why the execution paused
+        if matches!(
+            self.termination,
+            Some(TerminationReason::StepLimit | TerminationReason::TimedOut)
+        ) {
+            self.termination = None;
+        }
+
+        if self.detect_loops && self.check_for_loop() {
+            return Err(ExecutionError::InfiniteLoopDetected {
+                position: self.pointer.position,
+            });
+        }
+
+        for _ in 0..=self.threads.len() {
+            if TRACE {
+                self.trace()?;
+            }
+            self.step_pointer()?;
+
+            if self.terminated {
+                break;
+            }
+
+            if let Some(next) = self.threads.pop_front() {
+                let current = self.switch_to(next);
+                if !mem::take(&mut self.thread_ended) {
+                    self.threads.push_back(current);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Every pointer's state, starting with the current pointer:
+    /// everything that determines what a deterministic program does next,
+    /// as long as it doesn't modify itself.
+    fn pointer_states(&self) -> impl Iterator<Item = (&InstructionPointer, &[isize], bool)> {
+        std::iter::once((&self.pointer, self.stack.items.as_slice(), self.string_mode)).chain(
+            self.threads.iter().map(|thread| {
+                (
+                    &thread.pointer,
+                    thread.stack.items.as_slice(),
+                    thread.string_mode,
+                )
+            }),
+        )
+    }
+
+    /// Whether the execution has returned to the state saved by loop detection,
+    /// saving the current state instead when it's time to (see `LoopCheck`).
+    fn check_for_loop(&mut self) -> bool {
+        let mut check = match self.loop_check.take() {
+            Some(check) => check,
+            None => LoopCheck {
+                saved: self.saved_pointer_states(),
+                steps_since_save: 0,
+                steps_until_save: 1,
+            },
+        };
+
+        let repeated = check.steps_since_save > 0
+            && check.saved.len() == self.threads.len() + 1
+            && check.saved.iter().zip(self.pointer_states()).all(
+                |(saved, (pointer, stack, string_mode))| {
+                    saved.pointer == *pointer
+                        && saved.stack == stack
+                        && saved.string_mode == string_mode
+                },
+            );
+
+        check.steps_since_save += 1;
+        if check.steps_since_save > check.steps_until_save {
+            check.steps_since_save = 1;
+            check.steps_until_save *= 2;
+            check.saved = self.saved_pointer_states();
+        }
+        self.loop_check = Some(check);
+
+        repeated
+    }
+
+    fn saved_pointer_states(&self) -> Vec<PointerState> {
+        self.pointer_states()
+            .map(|(pointer, stack, string_mode)| PointerState {
+                pointer: *pointer,
+                stack: stack.to_vec(),
+                string_mode,
+            })
+            .collect()
+    }
+
+    /// Makes `thread` the current instruction pointer, returning the previous one.
+    fn switch_to(&mut self, thread: Thread) -> Thread {
+        Thread {
+            pointer: mem::replace(&mut self.pointer, thread.pointer),
+            stack: mem::replace(&mut self.stack, thread.stack),
+            string_mode: mem::replace(&mut self.string_mode, thread.string_mode),
+        }
+    }
+
+    /// The instruction pointers other than `pointer`, in the order they will execute.
+    pub fn threads(&self) -> &VecDeque<Thread> {
+        &self.threads
+    }
+
+    fn step_pointer(&mut self) -> ExecutionResult {
+        if let Some(on_step) = &mut self.on_step {
+            on_step(&StepRecord {
+                instruction_count: self.instruction_count,
+                position: self.pointer.position,
+                direction: self.pointer.direction(),
+                instruction: self.program.get(&self.pointer.position),
+                stack: self.stack.items(),
+            });
+        }
+
+        self.instruction_count += 1;
+
+        let position = self.pointer.position;
+        let underflows = self.stack.underflows;
+
+        let instruction = self.program.get(&position);
+        if self.profile {
+            *self.heatmap.entry(position).or_insert(0) += 1;
+            if !self.string_mode || instruction == '"' {
+                *self.histogram.entry(instruction).or_insert(0) += 1;
+            }
+        }
+
+        self.execute(instruction)?;
+
+        if self.track_underflow && self.stack.underflows > underflows {
+            *self.underflows.entry(position).or_insert(0) += self.stack.underflows - underflows;
+        }
+
+        // do not move the pointer when terminating
+        if !self.terminated {
+            self.advance()?;
+        }
+
+        Ok(())
+    }
+
+    /// Executes an instruction as if it were at the pointer's position,
+    /// without moving the pointer on afterwards.
+    fn execute(&mut self, instruction: char) -> ExecutionResult {
+        // after these, returning to an earlier state doesn't mean the program is stuck
+        if matches!(instruction, 'p' | '?' | '&' | '~' | '.' | ',') {
+            self.loop_check = None;
+        }
+
+        // https://esolangs.org/wiki/Befunge#Instructions
+        match instruction {
+            '"' => self.string_mode = !self.string_mode,
+            c if self.string_mode => self.stack.push(self.char_value(c)),
+            '^' => self.pointer.face(PointerDirection::Up),
+            'v' => self.pointer.face(PointerDirection::Down),
+            '>' => self.pointer.face(PointerDirection::Right),
+            '<' => self.pointer.face(PointerDirection::Left),
+            // reflect
+            'r' if self.dialect == Dialect::Funge98 => self.pointer.reverse(),
+            // turn left
+            '[' if self.dialect == Dialect::Funge98 => self.pointer.turn_left(),
+            // turn right
+            ']' if self.dialect == Dialect::Funge98 => self.pointer.turn_right(),
+            // set the pointer's movement to an arbitrary vector
+            'x' if self.dialect == Dialect::Funge98 => {
+                let dy = self.pop()?;
+                let dx = self.pop()?;
+                self.pointer.delta = (dx, dy);
+            }
+            // jump over everything up to the next ;
+            ';' if self.dialect == Dialect::Funge98 => self.skip_comment()?,
+            // iterate the next instruction
+            'k' if self.dialect == Dialect::Funge98 => self.iterate()?,
+            // jump forward (or backward) over cells
+            'j' if self.dialect == Dialect::Funge98 => self.jump()?,
+            // fetch the next character
+            '\'' if self.dialect == Dialect::Funge98 => {
+                self.advance()?;
+                self.stack
+                    .push(self.char_value(self.program.get(&self.pointer.position)));
+            }
+            '?' => {
+                let direction = self.rng.gen();
+                self.pointer.face(direction);
+            }
+            '_' => {
+                // horizontal if
+                let top = self.pop()?;
+                if top == 0 {
+                    self.pointer.face(PointerDirection::Right);
+                } else {
+                    self.pointer.face(PointerDirection::Left);
+                }
+            }
+            // vertical if
+            '|' => {
+                let top = self.pop()?;
+                if top == 0 {
+                    self.pointer.face(PointerDirection::Down);
+                } else {
+                    self.pointer.face(PointerDirection::Up);
+                }
+            }
+            // addition
+            '+' => {
+                let a = self.pop()?;
+                let b = self.pop()?;
+                let result = self.arithmetic(
+                    b,
+                    a,
+                    isize::checked_add,
+                    isize::wrapping_add,
+                    isize::saturating_add,
+                )?;
+                self.stack.push(result);
+            }
+            // subtraction
+            '-' => {
+                let a = self.pop()?;
+                let b = self.pop()?;
+                let result = self.arithmetic(
+                    b,
+                    a,
+                    isize::checked_sub,
+                    isize::wrapping_sub,
+                    isize::saturating_sub,
+                )?;
+                self.stack.push(result);
+            }
             // multiplication
             '*' => {
-                let a = self.stack.pop();
-                let b = self.stack.pop();
-                self.stack.push(a * b);
+                let a = self.pop()?;
+                let b = self.pop()?;
+                let result = self.arithmetic(
+                    b,
+                    a,
+                    isize::checked_mul,
+                    isize::wrapping_mul,
+                    isize::saturating_mul,
+                )?;
+                self.stack.push(result);
             }
             // division
             '/' => {
-                let a = self.stack.pop();
-                let b = self.stack.pop();
-                self.stack.push(b / a);
+                let a = self.nonzero_divisor()?;
+                let b = self.pop()?;
+                let result = self.arithmetic(
+                    b,
+                    a,
+                    isize::checked_div,
+                    isize::wrapping_div,
+                    isize::saturating_div,
+                )?;
+                self.stack.push(result);
             }
             // modulo
             '%' => {
-                let a = self.stack.pop();
-                let b = self.stack.pop();
-                self.stack.push(b % a);
+                let a = self.nonzero_divisor()?;
+                let b = self.pop()?;
+                // the only overflowing remainder (isize::MIN % -1) is zero, so wrapping saturates
+                let result = self.arithmetic(
+                    b,
+                    a,
+                    isize::checked_rem,
+                    isize::wrapping_rem,
+                    isize::wrapping_rem,
+                )?;
+                self.stack.push(result);
             }
             // logical not
             '!' => {
-                let b = self.stack.pop();
+                let b = self.pop()?;
                 if b == 0 {
                     self.stack.push(1);
                 } else {
@@ -260,8 +1477,8 @@ impl<R: Read, O: Write> ExecutionState<R, O> {
             }
             // greater than
             '`' => {
-                let a = self.stack.pop();
-                let b = self.stack.pop();
+                let a = self.pop()?;
+                let b = self.pop()?;
                 if let Ordering::Greater = b.cmp(&a) {
                     self.stack.push(1)
                 } else {
@@ -270,66 +1487,113 @@ impl<R: Read, O: Write> ExecutionState<R, O> {
             }
             // duplicate top of self.stack
             ':' => {
-                let a = self.stack.pop();
+                let a = self.pop()?;
                 self.stack.push(a);
                 self.stack.push(a);
             }
             // swap top of self.stack
             '\\' => {
-                let a = self.stack.pop();
-                let b = self.stack.pop();
+                let a = self.pop()?;
+                let b = self.pop()?;
                 self.stack.push(a);
                 self.stack.push(b);
             }
             // discard top of self.stack
             '$' => {
-                self.stack.pop();
+                self.pop()?;
             }
             '.' => {
-                write!(self.output, "{}", self.stack.pop())
+                let value = self.pop()?;
+                // a single write, so that writers like the CLI's `--every` sampling see the whole number
+                let text = format!("{}{}", value, self.numeric_output_separator);
+                self.output
+                    .write_all(text.as_bytes())
                     .map_err(|_| ExecutionError::OutputFailed)?;
             }
             ',' => {
-                write!(self.output, "{}", self.stack.pop() as u8 as char)
+                let value = self.pop()?;
+                let policy = if self.unicode {
+                    OutputCharPolicy::Unicode
+                } else {
+                    self.output_char
+                };
+                let c = match policy {
+                    OutputCharPolicy::Wrap => value as u8 as char,
+                    OutputCharPolicy::Error => {
+                        u8::try_from(value).map(char::from).map_err(|_| {
+                            ExecutionError::InvalidOutputChar {
+                                position: self.pointer.position,
+                                value,
+                            }
+                        })?
+                    }
+                    OutputCharPolicy::Unicode => value
+                        .try_into()
+                        .ok()
+                        .and_then(char::from_u32)
+                        .unwrap_or(char::REPLACEMENT_CHARACTER),
+                };
+                self.output
+                    .write_all(c.encode_utf8(&mut [0; 4]).as_bytes())
                     .map_err(|_| ExecutionError::OutputFailed)?;
             }
-            '#' => move_pointer(&mut self.pointer),
+            '#' => self.advance()?,
             // get
             'g' => {
-                let y = self.stack.pop();
-                let x = self.stack.pop();
+                let y = self.pop()?;
+                let x = self.pop()?;
+                let target = self.cell_target(x, y)?;
                 self.stack
-                    .push(isize::from(self.program.get(&Position { x, y }) as u8));
+                    .push(isize::from(self.program.get(&target) as u8));
             }
             // push
             'p' => {
-                let y = self.stack.pop();
-                let x = self.stack.pop();
-                let v = self.stack.pop();
-                self.program.set(&Position { x, y }, v as u8 as char);
+                let y = self.pop()?;
+                let x = self.pop()?;
+                let v = self.pop()?;
+                let target = self.cell_target(x, y)?;
+                if let Some(overwritten) = &mut self.overwritten {
+                    overwritten.push((target, self.program.stored(&target)));
+                }
+                self.program.set(&target, v as u8 as char);
             }
-            // get int from user
-            // TODO: does not actually work from stdin
+            // get int from user (-1 at the end of the input)
             '&' => {
-                let mut input = String::new();
-                self.input
-                    .read_to_string(&mut input)
-                    .map_err(|_| ExecutionError::InputFailed)?;
-                self.stack.push(input.trim().parse::<isize>().unwrap());
-            }
-            // get char from user
-            // TODO: does not actually work from stdin
+                let value = self.read_integer()?.unwrap_or(-1);
+                self.stack.push(value);
+            }
+            // get char from user (-1 at the end of the input)
             '~' => {
-                let mut input = String::new();
-                self.input
-                    .read_to_string(&mut input)
-                    .map_err(|_| ExecutionError::InputFailed)?;
-                self.stack
-                    .push(isize::from(input.chars().next().unwrap() as u8));
+                let byte = self.read_byte()?.map_or(-1, isize::from);
+                self.stack.push(byte);
             }
             '@' => {
+                if self.threads.is_empty() {
+                    self.terminated = true;
+                    self.termination = Some(TerminationReason::Ended);
+                } else {
+                    self.thread_ended = true;
+                }
+            }
+            // split off a new instruction pointer, moving in the opposite direction
+            't' if self.dialect == Dialect::Funge98 => {
+                let mut pointer = self.pointer;
+                pointer.reverse();
+                pointer.position = self.next_position(pointer)?;
+                self.threads.push_back(Thread {
+                    pointer,
+                    stack: self.stack.clone(),
+                    string_mode: false,
+                });
+            }
+            // clear the stack
+            'n' if self.dialect == Dialect::Funge98 => self.stack.clear(),
+            // quit with an exit code
+            'q' if self.dialect == Dialect::Funge98 => {
+                let code = self.pop()? as i32;
+                self.exit_code = Some(code);
                 self.terminated = true;
-                return Ok(()); // exit immediately (do not move the pointer when terminating)
+                self.termination = Some(TerminationReason::Quit(code));
             }
             c @ '0'..='9' => self.stack.push(c.to_digit(10).unwrap().try_into().unwrap()),
             ' ' => {}
@@ -341,33 +1605,391 @@ impl<R: Read, O: Write> ExecutionState<R, O> {
             }
         }
 
-        move_pointer(&mut self.pointer);
-
         Ok(())
     }
 }
 
-fn move_pointer(pointer: &mut InstructionPointer) {
-    match pointer.direction {
-        PointerDirection::Up => pointer.position.y -= 1,
-        PointerDirection::Down => pointer.position.y += 1,
-        PointerDirection::Right => pointer.position.x += 1,
-        PointerDirection::Left => pointer.position.x -= 1,
+/// The state of the machine just before it executed an instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct StepRecord {
+    pub instruction_count: u64,
+    pub position: Position,
+    /// The pointer's direction, or `None` if it's moving by a delta that isn't a unit vector.
+    pub direction: Option<PointerDirection>,
+    pub instruction: char,
+    pub stack: Vec<isize>,
+}
+
+/// Runs a program deterministically (seeding the random number generator with `seed`)
+/// for at most `max_steps` instructions, recording each step through `set_on_step`.
+/// Every pointer's instructions count towards `max_steps`, so a step of several pointers
+/// may be cut off partway.
+/// The records contain no timestamps, so they can be compared against a golden file.
+pub fn trace_to_json(
+    program: Program,
+    input: &[u8],
+    seed: u64,
+    max_steps: u64,
+) -> Result<Vec<StepRecord>, ExecutionError> {
+    let mut execution = ExecutionState::new_seeded(program, false, input, Vec::new(), seed);
+
+    let records = Rc::new(RefCell::new(Vec::new()));
+    let recorded = Rc::clone(&records);
+    execution.set_on_step(Box::new(move |record| {
+        recorded.borrow_mut().push(record.clone())
+    }));
+
+    while !execution.terminated && (records.borrow().len() as u64) < max_steps {
+        execution.step()?;
+    }
+
+    let mut records = records.take();
+    records.truncate(max_steps.try_into().unwrap_or(usize::MAX));
+    Ok(records)
+}
+
+impl<R: Read, O: Write> ExecutionState<R, O> {
+    /// Reads the next byte of input, or `None` at the end of the input.
+    fn read_byte(&mut self) -> Result<Option<u8>, ExecutionError> {
+        if let Some(byte) = self.peeked_input.take() {
+            return Ok(Some(byte));
+        }
+
+        let mut buffer = [0; 1];
+        loop {
+            match self.input.read(&mut buffer) {
+                Ok(0) => return Ok(None),
+                Ok(_) => return Ok(Some(buffer[0])),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => return Err(ExecutionError::InputFailed),
+            }
+        }
+    }
+
+    /// Reads the next integer from the input, skipping any non-numeric bytes before it,
+    /// and leaving the input just after its last digit.
+    /// Returns `None` if the input ends before an integer is found.
+    fn read_integer(&mut self) -> Result<Option<isize>, ExecutionError> {
+        let mut negative = false;
+        let mut byte = self.read_byte()?;
+        loop {
+            match byte {
+                None => return Ok(None),
+                Some(b'0'..=b'9') => break,
+                Some(b'-') => {
+                    byte = self.read_byte()?;
+                    if matches!(byte, Some(b'0'..=b'9')) {
+                        negative = true;
+                        break;
+                    }
+                }
+                Some(_) => byte = self.read_byte()?,
+            }
+        }
+
+        let mut value: isize = 0;
+        while let Some(digit @ b'0'..=b'9') = byte {
+            value = value
+                .wrapping_mul(10)
+                .wrapping_add(isize::from(digit - b'0'));
+            byte = self.read_byte()?;
+        }
+        self.peeked_input = byte;
+
+        Ok(Some(
+            if negative {
+                value.wrapping_neg()
+            } else {
+                value
+            },
+        ))
+    }
+
+    /// Moves the pointer one cell in its direction, wrapping around according to `wrap`.
+    fn advance(&mut self) -> ExecutionResult {
+        self.pointer.position = self.next_position(self.pointer)?;
+
+        Ok(())
+    }
+
+    /// Where the pointer would be after moving one cell in its direction,
+    /// wrapping around according to `wrap`.
+    /// Without wrapping, fails if the move would take it past the largest or smallest possible coordinate.
+    fn next_position(&self, pointer: InstructionPointer) -> Result<Position, ExecutionError> {
+        let (min, max) = match self.wrap_bounds() {
+            Some(bounds) => bounds,
+            None => {
+                return moved(&pointer).ok_or(ExecutionError::PointerOverflow {
+                    position: self.pointer.position,
+                })
+            }
+        };
+
+        let (dx, dy) = pointer.delta;
+        Ok(Position {
+            x: wrapped_coordinate(pointer.position.x, dx, 1, min.x, max.x),
+            y: wrapped_coordinate(pointer.position.y, dy, 1, min.y, max.y),
+        })
+    }
+
+    /// The corners of the area the pointer wraps around in, or `None` if it doesn't wrap.
+    fn wrap_bounds(&self) -> Option<(Position, Position)> {
+        match self.wrap {
+            Wrapping::Unbounded => None,
+            Wrapping::Extent => self.program.extent(),
+            Wrapping::Befunge93 => Some((
+                Position { x: 0, y: 0 },
+                Position {
+                    x: BEFUNGE93_WIDTH - 1,
+                    y: BEFUNGE93_HEIGHT - 1,
+                },
+            )),
+        }
+    }
+
+    /// Walks along the pointer's path (without moving it), returning the first position
+    /// where `found` is true of the cell's contents.
+    /// Returns `None` if the walk returns to the pointer,
+    /// or if the pointer isn't wrapping and the walk leaves the program.
+    fn search_path(
+        &self,
+        found: impl Fn(char) -> bool,
+    ) -> Result<Option<Position>, ExecutionError> {
+        let (min, max) = match self.program.extent() {
+            Some(extent) => extent,
+            None => return Ok(None),
+        };
+
+        let mut pointer = self.pointer;
+        loop {
+            pointer.position = self.next_position(pointer)?;
+
+            let position = pointer.position;
+            if position == self.pointer.position {
+                return Ok(None);
+            }
+            let outside = position.x < min.x
+                || position.x > max.x
+                || position.y < min.y
+                || position.y > max.y;
+            if outside && self.wrap == Wrapping::Unbounded {
+                return Ok(None);
+            }
+
+            if found(self.program.get(&position)) {
+                return Ok(Some(position));
+            }
+        }
+    }
+
+    /// Moves the pointer onto the `;` that closes the comment it is on,
+    /// so that the comment executes in no time at all.
+    /// If the pointer would leave the program without finding one,
+    /// it wraps back around to the opening `;` instead.
+    fn skip_comment(&mut self) -> ExecutionResult {
+        if let Some(position) = self.search_path(|c| c == ';')? {
+            self.pointer.position = position;
+        }
+
+        Ok(())
+    }
+
+    /// The value that string mode pushes for a character.
+    fn char_value(&self, c: char) -> isize {
+        if self.unicode {
+            c as isize
+        } else {
+            isize::from(c as u8)
+        }
+    }
+
+    /// Pops a count and executes the next instruction along the pointer's path that many times,
+    /// then skips over it (unless it moved the pointer itself).
+    fn iterate(&mut self) -> ExecutionResult {
+        let count = self.pop()?;
+        let start = self.pointer.position;
+
+        let target = match self.search_path(|c| c != ' ')? {
+            Some(target) => target,
+            None => return Ok(()),
+        };
+        let instruction = self.program.get(&target);
+
+        for _ in 0..count.max(0) {
+            self.execute(instruction)?;
+            if self.terminated {
+                return Ok(());
+            }
+        }
+
+        if self.pointer.position == start {
+            self.pointer.position = target;
+        }
+
+        Ok(())
+    }
+
+    /// Pops a distance and moves the pointer that many cells along its path
+    /// (backward if the distance is negative), without executing the cells in between.
+    fn jump(&mut self) -> ExecutionResult {
+        let distance = self.pop()?;
+
+        let position = self.pointer.position;
+        let (dx, dy) = self.pointer.delta;
+        self.pointer.position = match self.wrap_bounds() {
+            // wrapping around after every step ends up in the same place as wrapping once
+            Some((min, max)) => Position {
+                x: wrapped_coordinate(position.x, dx, distance, min.x, max.x),
+                y: wrapped_coordinate(position.y, dy, distance, min.y, max.y),
+            },
+            None => {
+                let moved =
+                    |start: isize, delta: isize| start.checked_add(delta.checked_mul(distance)?);
+                match (moved(position.x, dx), moved(position.y, dy)) {
+                    (Some(x), Some(y)) => Position { x, y },
+                    _ => return Err(ExecutionError::PointerOverflow { position }),
+                }
+            }
+        };
+
+        Ok(())
+    }
+
+    /// Pops from the stack, failing if it's empty and `strict_stack` is set.
+    fn pop(&mut self) -> Result<isize, ExecutionError> {
+        if self.strict_stack && self.stack.is_empty() {
+            return Err(ExecutionError::StackUnderflow {
+                position: self.pointer.position,
+                instruction: self.program.get(&self.pointer.position),
+            });
+        }
+
+        Ok(self.stack.pop())
     }
+
+    fn nonzero_divisor(&mut self) -> Result<isize, ExecutionError> {
+        match self.pop()? {
+            0 => Err(ExecutionError::DivisionByZero {
+                position: self.pointer.position,
+            }),
+            a => Ok(a),
+        }
+    }
+
+    /// Applies the variant of an arithmetic operation selected by `arithmetic`.
+    fn arithmetic(
+        &self,
+        a: isize,
+        b: isize,
+        checked: fn(isize, isize) -> Option<isize>,
+        wrapping: fn(isize, isize) -> isize,
+        saturating: fn(isize, isize) -> isize,
+    ) -> Result<isize, ExecutionError> {
+        match self.arithmetic {
+            ArithmeticMode::Wrapping => Ok(wrapping(a, b)),
+            ArithmeticMode::Saturating => Ok(saturating(a, b)),
+            ArithmeticMode::Checked => checked(a, b).ok_or(ExecutionError::ArithmeticOverflow {
+                position: self.pointer.position,
+            }),
+        }
+    }
+
+    /// The cell that `g` or `p` should access for the given coordinates.
+    fn cell_target(&self, x: isize, y: isize) -> Result<Position, ExecutionError> {
+        let target = if x >= 0 && y >= 0 {
+            Position { x, y }
+        } else {
+            match self.negative_coordinates {
+                NegativeCoordinates::Allow => Position { x, y },
+                NegativeCoordinates::Clamp => Position {
+                    x: x.max(0),
+                    y: y.max(0),
+                },
+                NegativeCoordinates::Error => {
+                    return Err(ExecutionError::NegativeCoordinates {
+                        position: self.pointer.position,
+                        target: Position { x, y },
+                    })
+                }
+            }
+        };
+
+        let limit = match self.field_limit {
+            Some(limit) => limit,
+            None => return Ok(target),
+        };
+        if (0..limit.width).contains(&target.x) && (0..limit.height).contains(&target.y) {
+            return Ok(target);
+        }
+
+        match limit.out_of_bounds {
+            OutOfBounds::Wrap => Ok(Position {
+                x: target.x.rem_euclid(limit.width),
+                y: target.y.rem_euclid(limit.height),
+            }),
+            OutOfBounds::Error => Err(ExecutionError::OutOfBounds {
+                position: self.pointer.position,
+                target,
+            }),
+        }
+    }
+}
+
+/// Where a coordinate ends up after `steps` moves of `delta`,
+/// wrapping around to stay between `min` and `max` (inclusive).
+fn wrapped_coordinate(start: isize, delta: isize, steps: isize, min: isize, max: isize) -> isize {
+    // none of this can overflow when widened
+    let (start, delta, steps, min, max) = (
+        start as i128,
+        delta as i128,
+        steps as i128,
+        min as i128,
+        max as i128,
+    );
+    (min + (start - min + delta * steps).rem_euclid(max - min + 1)) as isize
+}
+
+/// Where the pointer would be after moving by its delta, or `None` if that would overflow.
+fn moved(pointer: &InstructionPointer) -> Option<Position> {
+    let (dx, dy) = pointer.delta;
+    Some(Position {
+        x: pointer.position.x.checked_add(dx)?,
+        y: pointer.position.y.checked_add(dy)?,
+    })
 }
 
 #[cfg(test)]
 mod tests {
-    use std::str::FromStr;
+    use std::{cell::RefCell, rc::Rc, str::FromStr};
 
     use crate::{
-        examples::{ERATOSTHENES, FACTORIAL, HELLO_WORLD, QUINE},
-        execution::{ExecutionError, ExecutionState},
-        program::{Position, Program},
+        dialect::Dialect,
+        examples::{ERATOSTHENES, FACTORIAL, HELLO_WORLD, INPUT, QUINE, RNG},
+        execution::{
+            is_instruction, trace_to_json, ArithmeticMode, EmptyFieldLimit, ExecutionBuilder,
+            ExecutionError, ExecutionState, FieldLimit, NegativeCoordinates, OutOfBounds,
+            OutputCharPolicy, PointerDirection, RunOutcome, Stack, StepRecord, TerminationReason,
+            Wrapping,
+        },
+        program::{extract_fenced_program, Position, Program},
     };
 
     pub type GenericResult = Result<(), Box<dyn std::error::Error>>;
 
+    /// A writer whose contents can still be read after it's boxed up as a trace output.
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl std::io::Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
     #[test]
     fn hello_world() -> GenericResult {
         let program = Program::from_str(HELLO_WORLD)?;
@@ -391,7 +2013,7 @@ mod tests {
         let mut execution = ExecutionState::new(program, false, input.as_slice(), output);
         execution.run()?;
         assert_eq!(
-            "2357111317192329313741434753596167717379",
+            "2 3 5 7 11 13 17 19 23 29 31 37 41 43 47 53 59 61 67 71 73 79 ",
             String::from_utf8(execution.output).unwrap()
         );
 
@@ -420,7 +2042,7 @@ mod tests {
         let output = Vec::new();
         let mut execution = ExecutionState::new(program, false, input.as_slice(), output);
         execution.run()?;
-        assert_eq!("120", String::from_utf8(execution.output).unwrap());
+        assert_eq!("120 ", String::from_utf8(execution.output).unwrap());
 
         Ok(())
     }
@@ -446,4 +2068,1744 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn valid_instructions_membership() {
+        for dialect in [Dialect::Befunge93, Dialect::Funge98] {
+            assert!(is_instruction('@', dialect));
+            assert!(is_instruction('?', dialect));
+            assert!(is_instruction('p', dialect));
+            assert!(!is_instruction('z', dialect));
+        }
+        assert!(!is_instruction('j', Dialect::Befunge93));
+        assert!(is_instruction('j', Dialect::Funge98));
+    }
+
+    #[test]
+    fn valid_instructions_match_step() -> GenericResult {
+        for dialect in [Dialect::Befunge93, Dialect::Funge98] {
+            for c in (0..=u8::MAX).map(char::from) {
+                // seed the stack so that division and modulo have a non-zero divisor
+                let mut program = Program::from_str("11")?;
+                program.set(&Position { x: 2, y: 0 }, c);
+                let input = *b"1";
+                let mut execution =
+                    ExecutionState::new(program, false, input.as_slice(), Vec::new());
+                execution.dialect = dialect;
+                execution.step()?;
+                execution.step()?;
+                let recognized = !matches!(
+                    execution.step(),
+                    Err(ExecutionError::UnrecognizedInstruction { .. })
+                );
+                assert_eq!(
+                    recognized,
+                    is_instruction(c, dialect),
+                    "{:?} in {:?}",
+                    c,
+                    dialect
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn manual_repositioning() -> GenericResult {
+        let program = Program::from_str("1.@\n2\n.")?;
+        let input = [];
+        let output = Vec::new();
+        let mut execution = ExecutionState::new(program, false, input.as_slice(), output);
+
+        execution.set_position(Position { x: 0, y: 1 });
+        execution.set_direction(PointerDirection::Down);
+        execution.step()?; // 2
+        execution.step()?; // .
+        assert_eq!(execution.pointer.position, Position { x: 0, y: 3 });
+
+        execution.set_position(Position { x: 0, y: 0 });
+        execution.set_direction(PointerDirection::Right);
+        execution.run()?;
+        assert_eq!("2 1 ", String::from_utf8(execution.output).unwrap());
+
+        Ok(())
+    }
+
+    #[test]
+    fn track_underflow() -> GenericResult {
+        let program = Program::from_str("1+.$@")?;
+        let input = [];
+        let output = Vec::new();
+        let mut execution = ExecutionState::new(program, false, input.as_slice(), output);
+        execution.track_underflow = true;
+        execution.run()?;
+
+        assert_eq!("1 ", String::from_utf8(execution.output.clone()).unwrap());
+        assert_eq!(execution.underflow_count(), 2);
+        assert_eq!(
+            execution
+                .underflow_positions()
+                .get(&Position { x: 1, y: 0 }),
+            Some(&1)
+        );
+        assert_eq!(
+            execution
+                .underflow_positions()
+                .get(&Position { x: 3, y: 0 }),
+            Some(&1)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn trace_records_steps() -> GenericResult {
+        let records = trace_to_json(Program::from_str("12+.@")?, &[], 0, 100)?;
+
+        assert_eq!(records.len(), 5);
+        assert_eq!(
+            records[3],
+            StepRecord {
+                instruction_count: 3,
+                position: Position { x: 3, y: 0 },
+                direction: Some(PointerDirection::Right),
+                instruction: '.',
+                stack: vec![3],
+            }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn trace_respects_max_steps() -> GenericResult {
+        let records = trace_to_json(Program::from_str("><")?, &[], 0, 10)?;
+
+        assert_eq!(records.len(), 10);
+
+        Ok(())
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn hello_world_trace_matches_golden() -> GenericResult {
+        let records = trace_to_json(Program::from_str(HELLO_WORLD)?, &[], 0, 10_000)?;
+        let json = records
+            .iter()
+            .map(serde_json::to_string)
+            .collect::<Result<Vec<_>, _>>()?
+            .join(",\n");
+
+        assert_eq!(
+            format!("[\n{}\n]\n", json),
+            include_str!("golden/hello_world_trace.json")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn stack_peak_depth() -> GenericResult {
+        let program = Program::from_str("123$$$45$$@")?;
+        let input = [];
+        let output = Vec::new();
+        let mut execution = ExecutionState::new(program, false, input.as_slice(), output);
+        execution.run()?;
+
+        assert_eq!(execution.stack.peak_depth(), 3);
+        assert!(execution.stack.items().is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn into_parts() -> GenericResult {
+        let program = Program::from_str("12.80p@")?;
+        let input = [];
+        let output = Vec::new();
+        let mut execution = ExecutionState::new(program, false, input.as_slice(), output);
+        execution.run()?;
+
+        let (program, stack, input, output) = execution.into_parts();
+        assert_eq!(program.get(&Position { x: 8, y: 0 }), '\u{1}');
+        assert_eq!(stack.items(), Vec::<isize>::new());
+        assert!(input.is_empty());
+        assert_eq!("2 ", String::from_utf8(output).unwrap());
+
+        Ok(())
+    }
+
+    #[test]
+    fn hello_world_from_markdown() -> GenericResult {
+        let markdown = format!(
+            "# Hello\n\nSays hello:\n\n```befunge\n{}```\n\nThat's all.\n",
+            HELLO_WORLD
+        );
+        let program = Program::from_str(&extract_fenced_program(&markdown).unwrap())?;
+        let input = [];
+        let output = Vec::new();
+        let mut execution = ExecutionState::new(program, false, input.as_slice(), output);
+        execution.run()?;
+        assert_eq!(
+            "Hello, World!\n",
+            String::from_utf8(execution.output).unwrap()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn stack_depth() {
+        let mut stack = Stack::new();
+        assert_eq!(stack.depth(), 0);
+
+        stack.push(1);
+        stack.push(2);
+        stack.push(3);
+        assert_eq!(stack.depth(), 3);
+
+        stack.pop();
+        assert_eq!(stack.depth(), 2);
+
+        stack.pop();
+        stack.pop();
+        assert_eq!(stack.depth(), 0);
+
+        // popping an empty stack gives 0 without going any deeper
+        assert_eq!(stack.pop(), 0);
+        assert_eq!(stack.depth(), 0);
+        assert!(stack.is_empty());
+
+        stack.push(4);
+        assert_eq!(stack.depth(), 1);
+        assert_eq!(stack.peak_depth(), 3);
+    }
+
+    #[test]
+    fn stack_from_vec() {
+        let mut stack = Stack::from_vec(vec![1, 2, 3]);
+        assert_eq!(stack.items(), vec![1, 2, 3]);
+
+        assert_eq!(stack.pop(), 3);
+        assert_eq!(stack.pop(), 2);
+        assert_eq!(stack.pop(), 1);
+        assert_eq!(stack.pop(), 0);
+    }
+
+    #[test]
+    fn halted_on() -> GenericResult {
+        let program = Program::from_str("v\n>1.@")?;
+        let input = [];
+        let output = Vec::new();
+        let mut execution = ExecutionState::new(program, false, input.as_slice(), output);
+
+        execution.step()?;
+        assert!(!execution.is_terminated());
+        assert_eq!(execution.halted_on(), None);
+
+        execution.run()?;
+        assert!(execution.is_terminated());
+        assert_eq!(execution.halted_on(), Some((Position { x: 3, y: 1 }, '@')));
+
+        Ok(())
+    }
+
+    #[test]
+    fn negative_coordinates() -> GenericResult {
+        // put 'A' at (-1, 0), then print the value at (0, 0)
+        let source = "88*1+01-0p00g.@";
+
+        let run = |mode| {
+            let program = Program::from_str(source).unwrap();
+            let input: &[u8] = &[];
+            let mut execution = ExecutionState::new(program, false, input, Vec::new());
+            execution.negative_coordinates = mode;
+            execution.run().map(|_| execution)
+        };
+
+        let allowed = run(NegativeCoordinates::Allow)?;
+        assert_eq!(allowed.program.get(&Position { x: -1, y: 0 }), 'A');
+        assert_eq!(String::from_utf8(allowed.output).unwrap(), "56 ");
+
+        let clamped = run(NegativeCoordinates::Clamp)?;
+        assert_eq!(clamped.program.get(&Position { x: -1, y: 0 }), ' ');
+        assert_eq!(String::from_utf8(clamped.output).unwrap(), "65 ");
+
+        assert!(matches!(
+            run(NegativeCoordinates::Error),
+            Err(ExecutionError::NegativeCoordinates {
+                position: Position { x: 9, y: 0 },
+                target: Position { x: -1, y: 0 },
+            })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn division_by_zero() -> GenericResult {
+        for source in ["50/.@", "50%.@"] {
+            let program = Program::from_str(source)?;
+            let input = [];
+            let output = Vec::new();
+            let mut execution = ExecutionState::new(program, false, input.as_slice(), output);
+
+            assert!(matches!(
+                execution.run(),
+                Err(ExecutionError::DivisionByZero {
+                    position: Position { x: 2, y: 0 }
+                })
+            ));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn input_reads_one_value_at_a_time() -> GenericResult {
+        let program = Program::from_str("&&&~@")?;
+        let input = b" 12 34\n-5x".as_slice();
+        let output = Vec::new();
+        let mut execution = ExecutionState::new(program, false, input, output);
+        execution.run()?;
+
+        assert_eq!(execution.stack.items(), vec![12, 34, -5, 120]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn input_example() -> GenericResult {
+        let program = Program::from_str(INPUT)?;
+        let input = b"42 a".as_slice();
+        let output = Vec::new();
+        let mut execution = ExecutionState::new(program, false, input, output);
+        execution.run()?;
+
+        assert_eq!("42 32 ", String::from_utf8(execution.output).unwrap());
+
+        Ok(())
+    }
+
+    #[test]
+    fn input_pushes_negative_one_at_end_of_input() -> GenericResult {
+        let program = Program::from_str("&~@")?;
+        let input = [];
+        let output = Vec::new();
+        let mut execution = ExecutionState::new(program, false, input.as_slice(), output);
+        execution.run()?;
+
+        assert_eq!(execution.stack.items(), vec![-1, -1]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn integer_input_skips_non_numeric_bytes() -> GenericResult {
+        let program = Program::from_str("&&&@")?;
+        let input = b"abc 7 - -3x".as_slice();
+        let output = Vec::new();
+        let mut execution = ExecutionState::new(program, false, input, output);
+        execution.run()?;
+
+        assert_eq!(execution.stack.items(), vec![7, -3, -1]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn wrapping_around_the_extent() -> GenericResult {
+        let program = Program::from_str("<  @")?;
+        let input = [];
+        let output = Vec::new();
+        let mut execution = ExecutionState::new(program, false, input.as_slice(), output);
+        execution.wrap = Wrapping::Extent;
+        execution.step()?;
+
+        assert_eq!(execution.pointer.position, Position { x: 3, y: 0 });
+
+        execution.run()?;
+        assert_eq!(execution.halted_on(), Some((Position { x: 3, y: 0 }, '@')));
+
+        Ok(())
+    }
+
+    #[test]
+    fn wrapping_around_fixed_bounds() -> GenericResult {
+        let mut program = Program::from_str("<  @")?;
+        program.ensure_bounds(10, 1);
+        let input = [];
+        let output = Vec::new();
+        let mut execution = ExecutionState::new(program, false, input.as_slice(), output);
+        execution.wrap = Wrapping::Extent;
+        execution.step()?;
+
+        assert_eq!(execution.pointer.position, Position { x: 9, y: 0 });
+
+        Ok(())
+    }
+
+    #[test]
+    fn wrapping_vertically_around_the_befunge93_playfield() -> GenericResult {
+        let program = Program::from_str("^")?;
+        let input = [];
+        let output = Vec::new();
+        let mut execution = ExecutionState::new(program, false, input.as_slice(), output);
+        execution.wrap = Wrapping::Befunge93;
+        execution.step()?;
+
+        assert_eq!(execution.pointer.position, Position { x: 0, y: 24 });
+
+        Ok(())
+    }
+
+    #[test]
+    fn trampoline_wraps_around_the_extent() -> GenericResult {
+        // jumping from the last column skips the first, so the 1 is never pushed
+        let program = Program::from_str("1.@#")?;
+        let input = [];
+        let output = Vec::new();
+        let mut execution = ExecutionState::new(program, false, input.as_slice(), output);
+        execution.wrap = Wrapping::Extent;
+        execution.set_position(Position { x: 3, y: 0 });
+        execution.step()?;
+
+        assert_eq!(execution.pointer.position, Position { x: 1, y: 0 });
+
+        execution.run()?;
+        assert_eq!(String::from_utf8(execution.output)?, "0 ");
+
+        Ok(())
+    }
+
+    #[test]
+    fn trampoline_wraps_around_the_befunge93_playfield() -> GenericResult {
+        let program = Program::from_str(&format!("1.@{}#", " ".repeat(76)))?;
+        let input = [];
+        let output = Vec::new();
+        let mut execution = ExecutionState::new(program, false, input.as_slice(), output);
+        execution.wrap = Wrapping::Befunge93;
+        execution.set_position(Position { x: 79, y: 0 });
+        execution.step()?;
+
+        assert_eq!(execution.pointer.position, Position { x: 1, y: 0 });
+
+        execution.run()?;
+        assert_eq!(String::from_utf8(execution.output)?, "0 ");
+
+        Ok(())
+    }
+
+    #[test]
+    fn unbounded_by_default() -> GenericResult {
+        let program = Program::from_str("<  @")?;
+        let input = [];
+        let output = Vec::new();
+        let mut execution = ExecutionState::new(program, false, input.as_slice(), output);
+        execution.step()?;
+
+        assert_eq!(execution.pointer.position, Position { x: -1, y: 0 });
+
+        Ok(())
+    }
+
+    #[test]
+    fn unicode_output() -> GenericResult {
+        let run = |unicode: bool| -> Result<Vec<u8>, ExecutionError> {
+            let program = Program::from_str(",@").unwrap();
+            let input: &[u8] = &[];
+            let mut execution = ExecutionState::new(program, false, input, Vec::new());
+            execution.unicode = unicode;
+            execution.stack = Stack::from_vec(vec![0x1F600]);
+            execution.run()?;
+            Ok(execution.output)
+        };
+
+        assert_eq!(String::from_utf8(run(true)?)?, "\u{1F600}");
+        assert_eq!(run(false)?, "\u{0}".as_bytes());
+
+        Ok(())
+    }
+
+    #[test]
+    fn output_char_policies() -> GenericResult {
+        let run = |policy: OutputCharPolicy, value: isize| -> Result<String, ExecutionError> {
+            let program = Program::from_str(",@").unwrap();
+            let mut execution = ExecutionState::builder(program)
+                .output_char(policy)
+                .build(std::io::empty(), Vec::new());
+            execution.stack = Stack::from_vec(vec![value]);
+            execution.run()?;
+            Ok(String::from_utf8(execution.output).unwrap())
+        };
+
+        assert_eq!(run(OutputCharPolicy::Wrap, -1)?, "\u{FF}");
+        assert_eq!(run(OutputCharPolicy::Wrap, 300)?, ",");
+        assert_eq!(run(OutputCharPolicy::Wrap, 65)?, "A");
+
+        for value in [-1, 300] {
+            assert!(matches!(
+                run(OutputCharPolicy::Error, value),
+                Err(ExecutionError::InvalidOutputChar {
+                    position: Position { x: 0, y: 0 },
+                    value: v,
+                }) if v == value
+            ));
+        }
+        assert_eq!(run(OutputCharPolicy::Error, 255)?, "\u{FF}");
+
+        assert_eq!(run(OutputCharPolicy::Unicode, -1)?, "\u{FFFD}");
+        assert_eq!(run(OutputCharPolicy::Unicode, 300)?, "\u{12C}");
+        assert_eq!(run(OutputCharPolicy::Unicode, 65)?, "A");
+
+        assert_eq!(OutputCharPolicy::default(), OutputCharPolicy::Wrap);
+
+        Ok(())
+    }
+
+    #[test]
+    fn unicode_string_mode() -> GenericResult {
+        let program = Program::from_str("\"é\"@")?;
+        let input = [];
+        let mut execution = ExecutionState::new(program, false, input.as_slice(), Vec::new());
+        execution.unicode = true;
+        execution.run()?;
+        assert_eq!(execution.stack.items(), vec![0xE9]);
+
+        let program = Program::from_str("\"😀\"@")?;
+        let mut execution =
+            ExecutionState::new(program.clone(), false, input.as_slice(), Vec::new());
+        execution.unicode = true;
+        execution.run()?;
+        assert_eq!(execution.stack.items(), vec![0x1F600]);
+
+        let mut execution = ExecutionState::new(program, false, input.as_slice(), Vec::new());
+        execution.run()?;
+        assert_eq!(execution.stack.items(), vec![0x00]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn arithmetic_modes() -> GenericResult {
+        let run = |mode: ArithmeticMode| -> Result<Vec<isize>, ExecutionError> {
+            let program = Program::from_str("2*@").unwrap();
+            let input: &[u8] = &[];
+            let mut execution = ExecutionState::new(program, false, input, Vec::new());
+            execution.arithmetic = mode;
+            execution.stack = Stack::from_vec(vec![isize::MAX]);
+            execution.run()?;
+            Ok(execution.stack.items())
+        };
+
+        assert_eq!(run(ArithmeticMode::Wrapping)?, vec![-2]);
+        assert_eq!(run(ArithmeticMode::Saturating)?, vec![isize::MAX]);
+        assert!(matches!(
+            run(ArithmeticMode::Checked),
+            Err(ExecutionError::ArithmeticOverflow {
+                position: Position { x: 1, y: 0 }
+            })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn dividing_the_minimum_by_negative_one() -> GenericResult {
+        let run = |mode: ArithmeticMode| -> Result<Vec<isize>, ExecutionError> {
+            let program = Program::from_str("/@").unwrap();
+            let input: &[u8] = &[];
+            let mut execution = ExecutionState::new(program, false, input, Vec::new());
+            execution.arithmetic = mode;
+            execution.stack = Stack::from_vec(vec![isize::MIN, -1]);
+            execution.run()?;
+            Ok(execution.stack.items())
+        };
+
+        assert_eq!(run(ArithmeticMode::Wrapping)?, vec![isize::MIN]);
+        assert_eq!(run(ArithmeticMode::Saturating)?, vec![isize::MAX]);
+        assert!(run(ArithmeticMode::Checked).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn run_with_limit_stops_runaway_programs() -> GenericResult {
+        let program = Program::from_str(ERATOSTHENES)?;
+        let input = [];
+        let mut execution = ExecutionState::new(program, false, input.as_slice(), Vec::new());
+
+        assert_eq!(execution.run_with_limit(25)?, RunOutcome::LimitReached);
+        assert_eq!(execution.instruction_count, 25);
+        assert!(!execution.terminated);
+
+        Ok(())
+    }
+
+    #[test]
+    fn run_with_limit_reports_termination() -> GenericResult {
+        let program = Program::from_str(HELLO_WORLD)?;
+        let input = [];
+        let mut execution = ExecutionState::new(program, false, input.as_slice(), Vec::new());
+
+        assert_eq!(execution.run_with_limit(107)?, RunOutcome::Terminated);
+        assert_eq!(execution.instruction_count, 107);
+
+        Ok(())
+    }
+
+    #[test]
+    fn on_step_observes_each_instruction() -> GenericResult {
+        let program = Program::from_str(HELLO_WORLD)?;
+        let input = [];
+        let mut execution = ExecutionState::new(program, false, input.as_slice(), Vec::new());
+
+        let instructions = Rc::new(RefCell::new(String::new()));
+        let recorded = Rc::clone(&instructions);
+        execution.set_on_step(Box::new(move |record| {
+            recorded.borrow_mut().push(record.instruction)
+        }));
+        execution.run()?;
+
+        let expected = format!(
+            "{}{}{}",
+            "64+\"!dlroW ,olleH\">",
+            ":#_,#>".repeat(14),
+            ":#_@"
+        );
+        assert_eq!(*instructions.borrow(), expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn reflect() -> GenericResult {
+        let program = Program::from_str(">r")?;
+        let input = [];
+        let mut execution = ExecutionState::new(program, false, input.as_slice(), Vec::new());
+        execution.dialect = Dialect::Funge98;
+        execution.step()?;
+        execution.step()?;
+
+        assert_eq!(execution.pointer.direction(), Some(PointerDirection::Left));
+        assert_eq!(execution.pointer.position, Position { x: 0, y: 0 });
+
+        Ok(())
+    }
+
+    #[test]
+    fn reflect_is_rejected_in_befunge93() -> GenericResult {
+        let program = Program::from_str(">r")?;
+        let input = [];
+        let mut execution = ExecutionState::new(program, false, input.as_slice(), Vec::new());
+        execution.step()?;
+
+        assert!(matches!(
+            execution.step(),
+            Err(ExecutionError::UnrecognizedInstruction {
+                instruction: 'r',
+                ..
+            })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn comments_are_skipped() -> GenericResult {
+        let program = Program::from_str(";this is skipped;99+.@")?;
+        let input = [];
+        let mut execution = ExecutionState::new(program, false, input.as_slice(), Vec::new());
+        execution.dialect = Dialect::Funge98;
+        execution.run()?;
+
+        assert_eq!(String::from_utf8(execution.output)?, "18 ");
+        assert_eq!(execution.instruction_count, 6);
+
+        Ok(())
+    }
+
+    #[test]
+    fn unterminated_comment_wraps_back_to_itself() -> GenericResult {
+        let program = Program::from_str(";99+.@")?;
+        let input = [];
+        let mut execution = ExecutionState::new(program, false, input.as_slice(), Vec::new());
+        execution.dialect = Dialect::Funge98;
+        execution.run()?;
+
+        assert_eq!(String::from_utf8(execution.output)?, "18 ");
+
+        Ok(())
+    }
+
+    #[test]
+    fn iterate() -> GenericResult {
+        let program = Program::from_str("15k:@")?;
+        let input = [];
+        let mut execution = ExecutionState::new(program, false, input.as_slice(), Vec::new());
+        execution.dialect = Dialect::Funge98;
+        execution.run()?;
+
+        assert_eq!(execution.stack.items(), vec![1; 6]);
+        assert_eq!(execution.halted_on(), Some((Position { x: 4, y: 0 }, '@')));
+
+        Ok(())
+    }
+
+    #[test]
+    fn iterate_zero_times_skips_the_instruction() -> GenericResult {
+        let program = Program::from_str("10k.@")?;
+        let input = [];
+        let mut execution = ExecutionState::new(program, false, input.as_slice(), Vec::new());
+        execution.dialect = Dialect::Funge98;
+        execution.run()?;
+
+        assert_eq!(execution.stack.items(), vec![1]);
+        assert!(execution.output.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn iterate_over_a_direction_change() -> GenericResult {
+        let program = Program::from_str("2k v\n   @")?;
+        let input = [];
+        let mut execution = ExecutionState::new(program, false, input.as_slice(), Vec::new());
+        execution.dialect = Dialect::Funge98;
+        execution.run()?;
+
+        assert_eq!(execution.halted_on(), Some((Position { x: 3, y: 1 }, '@')));
+
+        Ok(())
+    }
+
+    #[test]
+    fn jump_forward() -> GenericResult {
+        let program = Program::from_str("1j12.@")?;
+        let input = [];
+        let mut execution = ExecutionState::new(program, false, input.as_slice(), Vec::new());
+        execution.dialect = Dialect::Funge98;
+        execution.run()?;
+
+        assert_eq!(String::from_utf8(execution.output)?, "2 ");
+
+        Ok(())
+    }
+
+    #[test]
+    fn jump_backward() -> GenericResult {
+        // jumping back 7 from the j lands on the first @, so execution continues from the 8
+        let program = Program::from_str("5.@8.@07-j")?;
+        let input = [];
+        let mut execution = ExecutionState::new(program, false, input.as_slice(), Vec::new());
+        execution.dialect = Dialect::Funge98;
+        execution.set_position(Position { x: 6, y: 0 });
+        execution.run()?;
+
+        assert_eq!(execution.halted_on(), Some((Position { x: 5, y: 0 }, '@')));
+        assert_eq!(String::from_utf8(execution.output)?, "8 ");
+
+        Ok(())
+    }
+
+    #[test]
+    fn jump_zero_is_a_no_op() -> GenericResult {
+        let program = Program::from_str("0j1.@")?;
+        let input = [];
+        let mut execution = ExecutionState::new(program, false, input.as_slice(), Vec::new());
+        execution.dialect = Dialect::Funge98;
+        execution.run()?;
+
+        assert_eq!(String::from_utf8(execution.output)?, "1 ");
+
+        Ok(())
+    }
+
+    #[test]
+    fn huge_jumps_wrap_without_stepping() -> GenericResult {
+        // jumping a multiple of the width lands back on the j
+        let program = Program::from_str("j1.@")?;
+        let mut execution = ExecutionState::builder(program)
+            .dialect(Dialect::Funge98)
+            .wrap(Wrapping::Extent)
+            .build(std::io::empty(), Vec::new());
+        execution.stack = Stack::from_vec(vec![isize::MAX - 3]);
+        execution.run()?;
+
+        assert_eq!(String::from_utf8(execution.output)?, "1 ");
+
+        Ok(())
+    }
+
+    #[test]
+    fn huge_jumps_without_wrapping() -> GenericResult {
+        let program = Program::from_str("j")?;
+        let mut execution = ExecutionState::builder(program)
+            .dialect(Dialect::Funge98)
+            .build(std::io::empty(), Vec::new());
+        execution.stack = Stack::from_vec(vec![1 << 40]);
+        execution.step()?;
+
+        assert_eq!(
+            execution.pointer.position,
+            Position {
+                x: (1 << 40) + 1,
+                y: 0
+            }
+        );
+
+        let program = Program::from_str(" j")?;
+        let mut execution = ExecutionState::builder(program)
+            .dialect(Dialect::Funge98)
+            .build(std::io::empty(), Vec::new());
+        execution.set_position(Position { x: 1, y: 0 });
+        execution.stack = Stack::from_vec(vec![isize::MAX]);
+
+        assert!(matches!(
+            execution.step(),
+            Err(ExecutionError::PointerOverflow {
+                position: Position { x: 1, y: 0 }
+            })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn moving_past_the_largest_coordinate_fails() -> GenericResult {
+        let program = Program::from_str("x")?;
+        let mut execution = ExecutionState::builder(program)
+            .dialect(Dialect::Funge98)
+            .build(std::io::empty(), Vec::new());
+        execution.stack = Stack::from_vec(vec![isize::MAX, 0]);
+        execution.step()?;
+        assert_eq!(
+            execution.pointer.position,
+            Position {
+                x: isize::MAX,
+                y: 0
+            }
+        );
+
+        assert!(matches!(
+            execution.step(),
+            Err(ExecutionError::PointerOverflow {
+                position: Position {
+                    x: isize::MAX,
+                    y: 0
+                }
+            })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn huge_deltas_wrap_around() -> GenericResult {
+        // a delta of isize::MAX is the same as -1 on a playfield 4 cells wide
+        let program = Program::from_str("x@.1")?;
+        let mut execution = ExecutionState::builder(program)
+            .dialect(Dialect::Funge98)
+            .wrap(Wrapping::Extent)
+            .build(std::io::empty(), Vec::new());
+        execution.stack = Stack::from_vec(vec![isize::MAX, 0]);
+        execution.run()?;
+
+        assert_eq!(String::from_utf8(execution.output)?, "1 ");
+
+        Ok(())
+    }
+
+    #[test]
+    fn wrapping_around_the_whole_coordinate_space() -> GenericResult {
+        let mut program = Program::from_str("")?;
+        program.set(
+            &Position {
+                x: isize::MAX,
+                y: 0,
+            },
+            '>',
+        );
+        program.set(
+            &Position {
+                x: isize::MIN,
+                y: 0,
+            },
+            '@',
+        );
+        let mut execution = ExecutionState::builder(program)
+            .wrap(Wrapping::Extent)
+            .build(std::io::empty(), Vec::new());
+        execution.set_position(Position {
+            x: isize::MAX,
+            y: 0,
+        });
+        execution.run()?;
+
+        assert_eq!(
+            execution.halted_on(),
+            Some((
+                Position {
+                    x: isize::MIN,
+                    y: 0
+                },
+                '@'
+            ))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn absolute_vector() -> GenericResult {
+        // moving two cells at a time skips over the @s in between
+        let program = Program::from_str("20x@1@2@+@.@@")?;
+        let input = [];
+        let mut execution = ExecutionState::new(program, false, input.as_slice(), Vec::new());
+        execution.dialect = Dialect::Funge98;
+        execution.run()?;
+
+        assert_eq!(String::from_utf8(execution.output)?, "3 ");
+
+        Ok(())
+    }
+
+    #[test]
+    fn absolute_vector_turns() -> GenericResult {
+        let program = Program::from_str("11x")?;
+        let input = [];
+        let mut execution = ExecutionState::new(program, false, input.as_slice(), Vec::new());
+        execution.dialect = Dialect::Funge98;
+        execution.run_with_limit(3)?;
+
+        assert_eq!(execution.pointer.delta, (1, 1));
+        assert_eq!(execution.pointer.position, Position { x: 3, y: 1 });
+
+        execution.pointer.turn_left();
+        assert_eq!(execution.pointer.delta, (1, -1));
+        execution.pointer.reverse();
+        assert_eq!(execution.pointer.delta, (-1, 1));
+        execution.pointer.turn_right();
+        assert_eq!(execution.pointer.delta, (-1, -1));
+
+        execution.set_direction(PointerDirection::Down);
+        assert_eq!(execution.pointer.delta, (0, 1));
+
+        Ok(())
+    }
+
+    #[test]
+    fn absolute_vector_in_a_cardinal_direction() -> GenericResult {
+        let program = Program::from_str("01x")?;
+        let input = [];
+        let mut execution = ExecutionState::new(program, false, input.as_slice(), Vec::new());
+        execution.dialect = Dialect::Funge98;
+        execution.run_with_limit(3)?;
+
+        assert_eq!(execution.pointer.direction(), Some(PointerDirection::Down));
+
+        Ok(())
+    }
+
+    #[test]
+    fn fetch_character() -> GenericResult {
+        let program = Program::from_str("'A.@")?;
+        let input = [];
+        let mut execution = ExecutionState::new(program, false, input.as_slice(), Vec::new());
+        execution.dialect = Dialect::Funge98;
+        execution.run()?;
+
+        assert_eq!(String::from_utf8(execution.output)?, "65 ");
+
+        Ok(())
+    }
+
+    #[test]
+    fn quit_with_exit_code() -> GenericResult {
+        let program = Program::from_str("5q")?;
+        let input = [];
+        let mut execution = ExecutionState::new(program, false, input.as_slice(), Vec::new());
+        execution.dialect = Dialect::Funge98;
+        execution.run()?;
+
+        assert!(execution.terminated);
+        assert_eq!(execution.exit_code, Some(5));
+        assert_eq!(execution.halted_on(), Some((Position { x: 1, y: 0 }, 'q')));
+
+        Ok(())
+    }
+
+    #[test]
+    fn clear_stack() -> GenericResult {
+        let program = Program::from_str("123n@")?;
+        let input = [];
+        let mut execution = ExecutionState::new(program, false, input.as_slice(), Vec::new());
+        execution.dialect = Dialect::Funge98;
+        execution.run()?;
+
+        assert!(execution.stack.items().is_empty());
+        assert_eq!(execution.stack.peak_depth(), 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn turns() -> GenericResult {
+        let run = |source: &str| -> Result<PointerDirection, ExecutionError> {
+            let program = Program::from_str(source).unwrap();
+            let input: &[u8] = &[];
+            let mut execution = ExecutionState::new(program, false, input, Vec::new());
+            execution.dialect = Dialect::Funge98;
+            execution.step()?;
+            execution.step()?;
+            Ok(execution.pointer.direction().unwrap())
+        };
+
+        assert_eq!(run(">]")?, PointerDirection::Down);
+        assert_eq!(run(">[")?, PointerDirection::Up);
+
+        Ok(())
+    }
+
+    #[test]
+    fn turning_four_times_returns_to_the_start() {
+        for direction in [
+            PointerDirection::Up,
+            PointerDirection::Down,
+            PointerDirection::Left,
+            PointerDirection::Right,
+        ] {
+            assert_eq!(direction.turn_left().turn_right(), direction);
+            assert_eq!(direction.turn_left().turn_left(), direction.reversed());
+            assert_eq!(
+                direction
+                    .turn_right()
+                    .turn_right()
+                    .turn_right()
+                    .turn_right(),
+                direction
+            );
+        }
+    }
+
+    #[test]
+    fn peek_empty_stack() {
+        let stack = Stack::from_vec(vec![]);
+
+        assert_eq!(stack.peek(), 0);
+    }
+
+    #[test]
+    fn peek_does_not_remove() {
+        let mut stack = Stack::from_vec(vec![1]);
+        stack.push(2);
+
+        assert_eq!(stack.peek(), 2);
+        assert_eq!(stack.items(), vec![1, 2]);
+        assert_eq!(stack.pop(), 2);
+        assert_eq!(stack.pop(), 1);
+        assert_eq!(stack.pop(), 0);
+    }
+
+    #[test]
+    fn seeded_executions_are_reproducible() -> GenericResult {
+        let run = |seed: u64| -> Result<Vec<u8>, ExecutionError> {
+            let program = Program::from_str(RNG).unwrap();
+            let input: &[u8] = &[];
+            let mut execution = ExecutionState::new_seeded(program, false, input, Vec::new(), seed);
+            execution.run_with_limit(2000)?;
+            Ok(execution.output)
+        };
+
+        let output = run(7)?;
+        assert!(output.len() > 10);
+        assert_eq!(run(7)?, output);
+        assert_ne!(run(8)?, output);
+
+        Ok(())
+    }
+
+    #[test]
+    fn reset_reseeds() -> GenericResult {
+        let program = Program::from_str(RNG)?;
+        let input = [];
+        let mut execution =
+            ExecutionState::new_seeded(program, false, input.as_slice(), Vec::new(), 7);
+        execution.run_with_limit(2000)?;
+        let first = std::mem::take(&mut execution.output);
+
+        execution.reset();
+        execution.run_with_limit(2000)?;
+
+        assert_eq!(execution.output, first);
+
+        Ok(())
+    }
+
+    #[test]
+    fn instruction_histogram() -> GenericResult {
+        let program = Program::from_str(HELLO_WORLD)?;
+        let input = [];
+        let mut execution = ExecutionState::new(program, false, input.as_slice(), Vec::new());
+        execution.profile = true;
+        execution.run()?;
+
+        let histogram = execution.instruction_histogram();
+        assert_eq!(histogram[&','], 14);
+        assert_eq!(histogram[&'>'], 15);
+        assert_eq!(histogram[&'@'], 1);
+        assert_eq!(histogram[&'"'], 2);
+        assert!(!histogram.contains_key(&'H'));
+
+        Ok(())
+    }
+
+    #[test]
+    fn position_heatmap() -> GenericResult {
+        let program = Program::from_str("9>1-:v\n ^   _@")?;
+        let input = [];
+        let mut execution = ExecutionState::new(program, false, input.as_slice(), Vec::new());
+        execution.profile = true;
+        execution.run()?;
+
+        let heatmap = execution.position_heatmap();
+        assert_eq!(heatmap[&Position { x: 0, y: 0 }], 1);
+        for x in 1..=5 {
+            assert_eq!(heatmap[&Position { x, y: 0 }], 9);
+        }
+        assert_eq!(heatmap[&Position { x: 5, y: 1 }], 9);
+        assert_eq!(heatmap[&Position { x: 3, y: 1 }], 8);
+        assert_eq!(heatmap[&Position { x: 6, y: 1 }], 1);
+        assert_eq!(heatmap.values().sum::<u64>(), execution.instruction_count);
+
+        Ok(())
+    }
+
+    #[test]
+    fn dense_program_runs_identically() -> GenericResult {
+        let run = |program: Program| -> Result<(Vec<u8>, Program, u64), ExecutionError> {
+            let input: &[u8] = &[];
+            let mut execution = ExecutionState::new(program, false, input, Vec::new());
+            execution.run()?;
+            Ok((
+                execution.output,
+                execution.program,
+                execution.instruction_count,
+            ))
+        };
+
+        let (sparse_output, sparse_program, sparse_count) = run(Program::from_str(ERATOSTHENES)?)?;
+        let (dense_output, dense_program, dense_count) =
+            run(Program::from_str(ERATOSTHENES)?.to_dense())?;
+
+        assert_eq!(dense_output, sparse_output);
+        assert_eq!(dense_program.to_string(), sparse_program.to_string());
+        assert_eq!(dense_count, sparse_count);
+
+        Ok(())
+    }
+
+    #[test]
+    fn field_limit() -> GenericResult {
+        let run = |out_of_bounds: OutOfBounds| -> Result<Program, ExecutionError> {
+            let program = Program::from_str("p@").unwrap();
+            let input: &[u8] = &[];
+            let mut execution = ExecutionState::new(program, false, input, Vec::new());
+            execution.field_limit = Some(FieldLimit::new(80, 25, out_of_bounds).unwrap());
+            execution.stack = Stack::from_vec(vec!['x' as isize, 100_000, 100_000]);
+            execution.run()?;
+            Ok(execution.program)
+        };
+
+        let wrapped = run(OutOfBounds::Wrap)?;
+        assert_eq!(wrapped.get(&Position { x: 0, y: 0 }), 'x');
+        assert_eq!(
+            wrapped.get(&Position {
+                x: 100_000,
+                y: 100_000
+            }),
+            ' '
+        );
+
+        assert!(matches!(
+            run(OutOfBounds::Error),
+            Err(ExecutionError::OutOfBounds {
+                position: Position { x: 0, y: 0 },
+                target: Position {
+                    x: 100_000,
+                    y: 100_000
+                },
+            })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn field_limits_must_have_cells() {
+        assert!(FieldLimit::new(1, 1, OutOfBounds::Wrap).is_ok());
+        for (width, height) in [(0, 25), (80, 0), (-1, 25)] {
+            assert_eq!(
+                FieldLimit::new(width, height, OutOfBounds::Wrap),
+                Err(EmptyFieldLimit { width, height })
+            );
+        }
+    }
+
+    #[test]
+    fn undo_steps() -> GenericResult {
+        // overwrites a stored cell, then stores a cell outside the program
+        let program = Program::from_str("55+1-:00p:99p@")?;
+        let mut execution = ExecutionState::new(program, false, std::io::empty(), Vec::new());
+
+        let mut undos = vec![];
+        let mut snapshots = vec![];
+        while !execution.terminated {
+            snapshots.push(execution.snapshot());
+            let (undo, result) = execution.step_with_undo();
+            result?;
+            undos.push(undo);
+        }
+        assert_eq!(execution.program.get(&Position { x: 0, y: 0 }), '\t');
+        assert_eq!(execution.program.get(&Position { x: 9, y: 9 }), '\t');
+
+        for (undo, snapshot) in undos.into_iter().zip(snapshots).rev() {
+            execution.undo_step(undo);
+            assert_eq!(execution.snapshot(), snapshot);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn snapshot_and_restore() -> GenericResult {
+        let program = Program::from_str(ERATOSTHENES)?;
+        let input = [];
+        let mut execution = ExecutionState::new(program, false, input.as_slice(), Vec::new());
+        for _ in 0..10 {
+            execution.step()?;
+        }
+        let snapshot = execution.snapshot();
+
+        for _ in 0..5 {
+            execution.step()?;
+        }
+        assert_ne!(execution.snapshot(), snapshot);
+        let ahead = execution.snapshot();
+
+        execution.restore(snapshot.clone());
+        assert_eq!(execution.snapshot(), snapshot);
+        assert_eq!(execution.instruction_count, 10);
+
+        for _ in 0..5 {
+            execution.step()?;
+        }
+        assert_eq!(execution.snapshot(), ahead);
+
+        Ok(())
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json_round_trip_resumes_execution() -> GenericResult {
+        let input = [];
+        let mut uninterrupted = ExecutionState::new(
+            Program::from_str(ERATOSTHENES)?,
+            false,
+            input.as_slice(),
+            Vec::new(),
+        );
+        uninterrupted.run()?;
+
+        let mut first = ExecutionState::new(
+            Program::from_str(ERATOSTHENES)?,
+            false,
+            input.as_slice(),
+            Vec::new(),
+        );
+        first.run_with_limit(2000)?;
+        let json = first.to_json()?;
+
+        let mut resumed = ExecutionState::from_json(&json, false, input.as_slice(), Vec::new())?;
+        assert_eq!(resumed.instruction_count, 2000);
+        resumed.run()?;
+
+        let mut output = first.output;
+        output.extend(resumed.output);
+        assert_eq!(output, uninterrupted.output);
+        assert_eq!(resumed.instruction_count, uninterrupted.instruction_count);
+
+        Ok(())
+    }
+
+    #[test]
+    fn trace_output_captures_trace() -> GenericResult {
+        let program = Program::from_str(HELLO_WORLD)?;
+        let input = [];
+        let mut execution = ExecutionState::new(program, true, input.as_slice(), Vec::new());
+        let trace = Rc::new(RefCell::new(Vec::new()));
+        execution.set_trace_output(Box::new(SharedBuffer(Rc::clone(&trace))));
+        execution.run()?;
+
+        let trace = String::from_utf8(trace.borrow().clone())?;
+        let lines: Vec<&str> = trace.lines().collect();
+        assert_eq!(lines.len() as u64, execution.instruction_count);
+        assert!(lines[0].ends_with("[   0] ( 0,  0) -> 6 | "));
+        assert!(lines.last().unwrap().contains("-> @"));
+
+        Ok(())
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json_trace_format() -> GenericResult {
+        use crate::execution::TraceFormat;
+
+        let program = Program::from_str(HELLO_WORLD)?;
+        let input = [];
+        let mut execution = ExecutionState::new(program, true, input.as_slice(), Vec::new());
+        execution.trace_format = TraceFormat::Json;
+        let trace = Rc::new(RefCell::new(Vec::new()));
+        execution.set_trace_output(Box::new(SharedBuffer(Rc::clone(&trace))));
+        execution.run_with_limit(3)?;
+
+        let trace = String::from_utf8(trace.borrow().clone())?;
+        let lines = trace
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<Result<Vec<serde_json::Value>, _>>()?;
+        assert_eq!(
+            lines,
+            vec![
+                serde_json::json!({"instruction_count": 0, "x": 0, "y": 0, "instruction": "6", "direction": "Right", "stack": []}),
+                serde_json::json!({"instruction_count": 1, "x": 1, "y": 0, "instruction": "4", "direction": "Right", "stack": [6]}),
+                serde_json::json!({"instruction_count": 2, "x": 2, "y": 0, "instruction": "+", "direction": "Right", "stack": [6, 4]}),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn builder_matches_new() -> GenericResult {
+        let input = [];
+        let mut built = ExecutionState::builder(Program::from_str(HELLO_WORLD)?)
+            .profile(true)
+            .build(input.as_slice(), Vec::new());
+        let mut constructed = ExecutionState::new(
+            Program::from_str(HELLO_WORLD)?,
+            false,
+            input.as_slice(),
+            Vec::new(),
+        );
+        constructed.profile = true;
+
+        built.run()?;
+        constructed.run()?;
+
+        assert_eq!(built.output, constructed.output);
+        assert_eq!(built.pointer, constructed.pointer);
+        assert_eq!(built.stack, constructed.stack);
+        assert_eq!(built.instruction_count, constructed.instruction_count);
+        assert_eq!(
+            built.instruction_histogram(),
+            constructed.instruction_histogram()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn builder_sets_options() -> GenericResult {
+        let input = [];
+        let execution = ExecutionBuilder::new(Program::from_str(HELLO_WORLD)?)
+            .wrap(Wrapping::Befunge93)
+            .arithmetic(ArithmeticMode::Checked)
+            .dialect(Dialect::Funge98)
+            .unicode(true)
+            .build(input.as_slice(), Vec::new());
+
+        assert_eq!(execution.wrap, Wrapping::Befunge93);
+        assert_eq!(execution.arithmetic, ArithmeticMode::Checked);
+        assert_eq!(execution.dialect, Dialect::Funge98);
+        assert!(execution.unicode);
+
+        Ok(())
+    }
+
+    #[test]
+    fn builder_seed_is_deterministic() -> GenericResult {
+        let program = Program::from_str(RNG)?;
+        let input = [];
+        let mut built = ExecutionState::builder(program.clone())
+            .seed(42)
+            .build(input.as_slice(), Vec::new());
+        let mut seeded =
+            ExecutionState::new_seeded(program, false, input.as_slice(), Vec::new(), 42);
+
+        built.run_with_limit(1_000)?;
+        seeded.run_with_limit(1_000)?;
+
+        assert_eq!(built.output, seeded.output);
+
+        Ok(())
+    }
+
+    #[test]
+    fn termination_reason() -> GenericResult {
+        let input = [];
+
+        let mut execution =
+            ExecutionState::new(Program::from_str("@")?, false, input.as_slice(), Vec::new());
+        assert_eq!(execution.termination, None);
+        execution.run()?;
+        assert_eq!(execution.termination, Some(TerminationReason::Ended));
+
+        let mut execution = ExecutionState::builder(Program::from_str("7q")?)
+            .dialect(Dialect::Funge98)
+            .build(input.as_slice(), Vec::new());
+        execution.run()?;
+        assert_eq!(execution.termination, Some(TerminationReason::Quit(7)));
+
+        execution.reset();
+        assert_eq!(execution.termination, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn termination_reason_step_limit() -> GenericResult {
+        let input = [];
+        let mut execution = ExecutionState::new(
+            Program::from_str("1.@")?,
+            false,
+            input.as_slice(),
+            Vec::new(),
+        );
+
+        execution.run_with_limit(1)?;
+        assert_eq!(execution.termination, Some(TerminationReason::StepLimit));
+
+        execution.step()?;
+        assert_eq!(execution.termination, None);
+
+        execution.run_with_limit(1)?;
+        assert_eq!(execution.termination, Some(TerminationReason::Ended));
+
+        Ok(())
+    }
+
+    #[test]
+    fn step_many_matches_stepping() -> GenericResult {
+        let input = [];
+        let mut batched = ExecutionState::new_seeded(
+            Program::from_str(ERATOSTHENES)?,
+            false,
+            input.as_slice(),
+            Vec::new(),
+            0,
+        );
+        let mut stepped = ExecutionState::new_seeded(
+            Program::from_str(ERATOSTHENES)?,
+            false,
+            input.as_slice(),
+            Vec::new(),
+            0,
+        );
+
+        assert_eq!(batched.step_many(1_000)?, 1_000);
+        for _ in 0..1_000 {
+            stepped.step()?;
+        }
+
+        assert_eq!(batched.snapshot(), stepped.snapshot());
+        assert_eq!(batched.output, stepped.output);
+
+        Ok(())
+    }
+
+    #[test]
+    fn step_many_stops_at_termination() -> GenericResult {
+        let input = [];
+        let mut execution = ExecutionState::new(
+            Program::from_str(HELLO_WORLD)?,
+            false,
+            input.as_slice(),
+            Vec::new(),
+        );
+
+        assert_eq!(execution.step_many(1_000)?, 107);
+        assert!(execution.terminated);
+        assert_eq!(execution.step_many(1_000)?, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn cardinal_instructions_set_unit_deltas() -> GenericResult {
+        for (instruction, delta) in [('^', (0, -1)), ('v', (0, 1)), ('<', (-1, 0)), ('>', (1, 0))] {
+            let program = Program::from_str(&instruction.to_string())?;
+            let input = [];
+            let mut execution = ExecutionState::new(program, false, input.as_slice(), Vec::new());
+            execution.set_direction(PointerDirection::Left);
+            execution.step()?;
+
+            assert_eq!(execution.pointer.delta, delta);
+            assert_eq!(
+                execution.pointer.position,
+                Position {
+                    x: delta.0,
+                    y: delta.1
+                }
+            );
+            assert_eq!(
+                execution.pointer.direction().map(PointerDirection::delta),
+                Some(delta)
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn diagonal_delta() -> GenericResult {
+        let program = Program::from_str("v\n\n  1\n   .\n    @")?;
+        let input = [];
+        let mut execution = ExecutionState::new(program, false, input.as_slice(), Vec::new());
+        execution.set_position(Position { x: 1, y: 1 });
+        execution.pointer.delta = (1, 1);
+        assert_eq!(execution.pointer.direction(), None);
+
+        execution.run()?;
+
+        assert_eq!(execution.halted_on(), Some((Position { x: 4, y: 4 }, '@')));
+        assert_eq!(String::from_utf8(execution.output)?, "1 ");
+
+        Ok(())
+    }
+
+    #[test]
+    fn split() -> GenericResult {
+        // the new pointer moves left from the t, printing 2, while the original prints 1
+        let program = Program::from_str("@.2 t1.@")?;
+        let input = [];
+        let mut execution = ExecutionState::new(program, false, input.as_slice(), Vec::new());
+        execution.dialect = Dialect::Funge98;
+        execution.set_position(Position { x: 4, y: 0 });
+
+        execution.step()?;
+        assert_eq!(execution.threads().len(), 1);
+
+        execution.run_with_limit(3)?;
+        assert_eq!(execution.threads().len(), 0);
+        assert!(!execution.terminated);
+
+        execution.run()?;
+        assert_eq!(execution.halted_on(), Some((Position { x: 0, y: 0 }, '@')));
+        assert_eq!(execution.instruction_count, 8);
+        assert_eq!(String::from_utf8(execution.output)?, "1 2 ");
+
+        Ok(())
+    }
+
+    #[test]
+    fn split_pointers_have_their_own_stacks() -> GenericResult {
+        let program = Program::from_str("@.+1 t1.@")?;
+        let input = [];
+        let mut execution = ExecutionState::new(program, false, input.as_slice(), Vec::new());
+        execution.dialect = Dialect::Funge98;
+        execution.set_position(Position { x: 5, y: 0 });
+        execution.stack.push(5);
+        execution.run()?;
+
+        assert_eq!(String::from_utf8(execution.output)?, "1 6 ");
+
+        Ok(())
+    }
+
+    #[test]
+    fn strict_stack_underflow() -> GenericResult {
+        let input = [];
+
+        let mut lenient = ExecutionState::new(
+            Program::from_str(".@")?,
+            false,
+            input.as_slice(),
+            Vec::new(),
+        );
+        lenient.run()?;
+        assert_eq!(String::from_utf8(lenient.output)?, "0 ");
+
+        let mut strict = ExecutionState::builder(Program::from_str(".@")?)
+            .strict_stack(true)
+            .build(input.as_slice(), Vec::new());
+        assert!(matches!(
+            strict.run(),
+            Err(ExecutionError::StackUnderflow {
+                position: Position { x: 0, y: 0 },
+                instruction: '.',
+            })
+        ));
+        assert!(strict.output.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn strict_stack_allows_popping_pushed_values() -> GenericResult {
+        let program = Program::from_str("12+.@")?;
+        let input = [];
+        let mut execution = ExecutionState::new(program, false, input.as_slice(), Vec::new());
+        execution.strict_stack = true;
+        execution.run()?;
+
+        assert_eq!(String::from_utf8(execution.output)?, "3 ");
+
+        Ok(())
+    }
+
+    #[test]
+    fn numeric_output_separator() -> GenericResult {
+        let run = |separator: &str| -> Result<String, Box<dyn std::error::Error>> {
+            let input = [];
+            let mut execution = ExecutionState::builder(Program::from_str("12.3.@")?)
+                .numeric_output_separator(separator)
+                .build(input.as_slice(), Vec::new());
+            execution.run()?;
+            Ok(String::from_utf8(execution.output)?)
+        };
+
+        assert_eq!(run(" ")?, "2 3 ");
+        assert_eq!(run("")?, "23");
+        assert_eq!(run("\n")?, "2\n3\n");
+
+        let input = [];
+        let mut execution = ExecutionState::new(
+            Program::from_str("12.3.@")?,
+            false,
+            input.as_slice(),
+            Vec::new(),
+        );
+        execution.run()?;
+        assert_eq!(String::from_utf8(execution.output)?, "2 3 ");
+
+        Ok(())
+    }
+
+    #[test]
+    fn detects_infinite_loops() -> GenericResult {
+        let program = Program::from_str("><")?;
+        let mut execution = ExecutionState::builder(program)
+            .detect_loops(true)
+            .build(std::io::empty(), std::io::sink());
+
+        assert!(matches!(
+            execution.run(),
+            Err(ExecutionError::InfiniteLoopDetected {
+                position: Position { x: 1, y: 0 }
+            })
+        ));
+        assert_eq!(execution.instruction_count, 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn detects_long_loops_after_a_prefix() -> GenericResult {
+        // fills the stack, then loops around a rectangle forever
+        let program = Program::from_str("1234v\nv   <\n>   ^")?;
+        let mut execution = ExecutionState::builder(program)
+            .detect_loops(true)
+            .build(std::io::empty(), std::io::sink());
+
+        assert!(matches!(
+            execution.run_with_limit(10_000),
+            Err(ExecutionError::InfiniteLoopDetected { .. })
+        ));
+        assert!(execution.instruction_count < 200);
+        assert_eq!(execution.stack.items(), vec![1, 2, 3, 4]);
+        // only one earlier state is kept, however long the program runs
+        assert_eq!(execution.loop_check.map(|check| check.saved.len()), Some(1));
+
+        Ok(())
+    }
+
+    #[test]
+    fn loop_detection_is_off_by_default() -> GenericResult {
+        let program = Program::from_str("><")?;
+        let mut execution = ExecutionState::new(program, false, std::io::empty(), std::io::sink());
+
+        assert_eq!(execution.run_with_limit(100)?, RunOutcome::LimitReached);
+
+        Ok(())
+    }
+
+    #[test]
+    fn loops_with_output_or_randomness_are_not_detected() -> GenericResult {
+        for source in ["1.", "v\n?<\n>^"] {
+            let program = Program::from_str(source)?;
+            let mut execution = ExecutionState::builder(program)
+                .detect_loops(true)
+                .build(std::io::empty(), std::io::sink());
+
+            assert_eq!(
+                execution.run_with_limit(1000)?,
+                RunOutcome::LimitReached,
+                "{}",
+                source
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn loops_that_change_the_stack_are_not_detected() -> GenericResult {
+        let program = Program::from_str("1")?;
+        let mut execution = ExecutionState::builder(program)
+            .detect_loops(true)
+            .build(std::io::empty(), std::io::sink());
+
+        assert_eq!(execution.run_with_limit(1000)?, RunOutcome::LimitReached);
+
+        Ok(())
+    }
+
+    #[test]
+    fn run_with_timeout_stops_runaway_programs() -> GenericResult {
+        let program = Program::from_str("><")?;
+        let mut execution = ExecutionState::new(program, false, std::io::empty(), std::io::sink());
+
+        let start = std::time::Instant::now();
+        assert_eq!(
+            execution.run_with_timeout(std::time::Duration::from_millis(50))?,
+            RunOutcome::TimedOut
+        );
+        assert!(start.elapsed() < std::time::Duration::from_secs(1));
+        assert!(execution.instruction_count > 0);
+        assert_eq!(execution.termination, Some(TerminationReason::TimedOut));
+
+        // running again continues where it left off
+        execution.step()?;
+        assert_eq!(execution.termination, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn run_with_timeout_reports_termination() -> GenericResult {
+        let program = Program::from_str(HELLO_WORLD)?;
+        let mut output = Vec::new();
+        let mut execution = ExecutionState::new(program, false, std::io::empty(), &mut output);
+
+        assert_eq!(
+            execution.run_with_timeout(std::time::Duration::from_secs(10))?,
+            RunOutcome::Terminated
+        );
+        assert_eq!(execution.instruction_count, 107);
+        assert_eq!(output, b"Hello, World!\n");
+
+        Ok(())
+    }
 }
@@ -1,49 +1,74 @@
 use std::{
-    fmt::Write,
-    io,
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    fmt::Write as _,
+    fs, io,
+    io::{Read, Write},
+    path::{Path, PathBuf},
     time::{Duration, Instant},
 };
 
 use crossterm::{
+    cursor::Show,
     event,
-    event::{poll, DisableMouseCapture, EnableMouseCapture, Event, KeyCode},
+    event::{
+        poll, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, MouseButton, MouseEvent,
+        MouseEventKind,
+    },
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
 use itertools::Itertools;
 use tui::{
     backend::{Backend, CrosstermBackend},
-    layout::{Alignment, Constraint, Direction, Layout},
-    style::{Color, Style},
-    widgets::{Block, Borders, Cell, List, ListItem, Paragraph, Row, Table, Wrap},
+    layout::{Alignment, Constraint, Direction, Layout, Rect},
+    style::{Color, Modifier, Style},
+    widgets::{Block, Borders, Cell, Gauge, List, ListItem, Paragraph, Row, Table, Wrap},
     Frame, Terminal,
 };
 
 use crate::{
-    execution::{ExecutionError, ExecutionState},
+    execution::{
+        ExecutionError, ExecutionResult, ExecutionSnapshot, ExecutionState, PointerDirection,
+        StepUndo,
+    },
     ide::HandleKeyResult::{Continue, Quit},
-    program::{Position, Program},
+    program::{Position, Program, BINARY_EXTENSION},
 };
 
-pub fn ide(program: Program) -> io::Result<()> {
-    // setup terminal
-    enable_raw_mode()?;
-    let mut stdout = io::stdout();
-    execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
-    let backend = CrosstermBackend::new(stdout);
-    let mut terminal = Terminal::new(backend)?;
-
-    // create app and run it
-    let res = run_ide(&mut terminal, program);
-
-    // restore terminal
-    disable_raw_mode()?;
-    execute!(
-        terminal.backend_mut(),
-        LeaveAlternateScreen,
-        DisableMouseCapture
-    )?;
-    terminal.show_cursor()?;
+/// Runs the IDE on a program loaded from `path`, which the `s` key saves edits back to.
+/// If `step_limit` is given, the program pauses once it has run that many instructions.
+pub fn ide(program: Program, path: &Path, step_limit: Option<u64>) -> io::Result<()> {
+    open(
+        program,
+        path,
+        IDEState {
+            step_limit,
+            ..IDEState::new()
+        },
+    )
+}
+
+/// Runs the IDE paused before the first instruction, with the view following the pointer,
+/// ready to step through the program.
+pub fn debug(program: Program, path: &Path, step_limit: Option<u64>) -> io::Result<()> {
+    open(
+        program,
+        path,
+        IDEState {
+            step_limit,
+            ..IDEState::debugging()
+        },
+    )
+}
+
+fn open(program: Program, path: &Path, ide_state: IDEState) -> io::Result<()> {
+    let guard = TerminalGuard::new(io::stdout())?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(io::stdout()))?;
+
+    let res = run_ide(&mut terminal, program, path, ide_state);
+
+    // restore the terminal before printing the error, so that it's visible
+    drop(guard);
 
     if let Err(err) = res {
         println!("{:?}", err)
@@ -52,13 +77,325 @@ pub fn ide(program: Program) -> io::Result<()> {
     Ok(())
 }
 
+/// Puts the terminal into raw mode on the alternate screen, with mouse capture,
+/// and restores it when dropped, even if the IDE fails or panics.
+struct TerminalGuard<W: Write> {
+    out: W,
+    raw_mode: bool,
+}
+
+impl<W: Write> TerminalGuard<W> {
+    fn new(out: W) -> io::Result<Self> {
+        TerminalGuard::enter(out, true)
+    }
+
+    /// Sets up the terminal by writing commands to `out`, and enables raw mode if `raw_mode`.
+    fn enter(out: W, raw_mode: bool) -> io::Result<Self> {
+        if raw_mode {
+            enable_raw_mode()?;
+        }
+        // from here on, dropping the guard undoes whatever was done
+        let mut guard = TerminalGuard { out, raw_mode };
+        execute!(guard.out, EnterAlternateScreen, EnableMouseCapture)?;
+
+        Ok(guard)
+    }
+}
+
+impl<W: Write> Drop for TerminalGuard<W> {
+    fn drop(&mut self) {
+        // errors can't be reported from here, and restoring as much as possible is all we can do
+        if self.raw_mode {
+            let _ = disable_raw_mode();
+        }
+        let _ = execute!(self.out, LeaveAlternateScreen, DisableMouseCapture, Show);
+    }
+}
+
+/// The user's notes on cells, which aren't part of the program,
+/// kept in a sidecar file next to it (see `Notes::path_for`).
+/// Each line of the file holds a cell's x and y coordinates and its note, separated by tabs.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+struct Notes(BTreeMap<Position, String>);
+
+impl Notes {
+    /// The sidecar file for the program at `program_path`, which has `.notes` appended.
+    fn path_for(program_path: &Path) -> PathBuf {
+        let mut path = program_path.as_os_str().to_owned();
+        path.push(".notes");
+        PathBuf::from(path)
+    }
+
+    /// Reads the notes in `path`, or no notes if it doesn't exist.
+    fn load(path: &Path) -> io::Result<Notes> {
+        match fs::read_to_string(path) {
+            Ok(text) => Notes::parse(&text),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Notes::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Writes the notes to `path`, removing the file instead if there aren't any.
+    fn save(&self, path: &Path) -> io::Result<()> {
+        if self.0.is_empty() {
+            return match fs::remove_file(path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            };
+        }
+
+        fs::write(path, self.to_text())
+    }
+
+    fn parse(text: &str) -> io::Result<Notes> {
+        text.lines()
+            .filter(|line| !line.is_empty())
+            .map(|line| {
+                let mut fields = line.splitn(3, '\t');
+                let x = fields.next().and_then(|x| x.parse().ok());
+                let y = fields.next().and_then(|y| y.parse().ok());
+                match (x, y, fields.next()) {
+                    (Some(x), Some(y), Some(note)) => Ok((Position { x, y }, note.to_string())),
+                    _ => Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("invalid note: {:?}", line),
+                    )),
+                }
+            })
+            .collect::<io::Result<_>>()
+            .map(Notes)
+    }
+
+    fn to_text(&self) -> String {
+        self.0
+            .iter()
+            .map(|(position, note)| format!("{}\t{}\t{}\n", position.x, position.y, note))
+            .collect()
+    }
+
+    fn get(&self, position: &Position) -> Option<&str> {
+        self.0.get(position).map(String::as_str)
+    }
+
+    /// Attaches `note` to the cell at `position`, or removes its note if `note` is blank.
+    fn set(&mut self, position: Position, note: &str) {
+        let note = note.trim();
+        if note.is_empty() {
+            self.0.remove(&position);
+        } else {
+            self.0.insert(position, note.to_string());
+        }
+    }
+}
+
+/// The instructions-per-second settings selected by the `1` through `5` keys.
+const SPEED_PRESETS: [usize; 5] = [1, 10, 100, 1_000, 10_000];
+
+/// How many instructions the `[` and `]` keys seek backward and forward by.
+const SEEK_DISTANCE: u64 = 100;
+
+/// How many instructions apart the timeline's snapshots are taken.
+const SNAPSHOT_INTERVAL: u64 = 100;
+
+/// Everything typed into the input panel, and how much of it the program has read.
+/// Typed input is kept when the execution restarts or rewinds, so it can be read again.
+struct InputBuffer {
+    bytes: Vec<u8>,
+    position: usize,
+}
+
+impl InputBuffer {
+    fn new() -> Self {
+        InputBuffer {
+            bytes: vec![],
+            position: 0,
+        }
+    }
+
+    fn unread(&self) -> &[u8] {
+        &self.bytes[self.position..]
+    }
+}
+
+impl Read for InputBuffer {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.unread().read(buf)?;
+        self.position += n;
+        Ok(n)
+    }
+}
+
+type IDEExecution = ExecutionState<InputBuffer, Vec<u8>>;
+
+/// A snapshot of the execution, along with how much output it had produced
+/// and how much input it had read, so that all three can be rewound together.
+#[derive(Clone)]
+struct Checkpoint {
+    snapshot: ExecutionSnapshot,
+    output_len: usize,
+    input_position: usize,
+}
+
+impl Checkpoint {
+    fn new(execution_state: &IDEExecution) -> Self {
+        Checkpoint {
+            snapshot: execution_state.snapshot(),
+            output_len: execution_state.output.len(),
+            input_position: execution_state.input.position,
+        }
+    }
+
+    fn restore(self, execution_state: &mut IDEExecution) {
+        execution_state.restore(self.snapshot);
+        execution_state.output.truncate(self.output_len);
+        execution_state.input.position = self.input_position;
+    }
+}
+
+/// Periodic checkpoints of the execution,
+/// so that it can be rewound to any earlier instruction by replaying from the nearest checkpoint.
+/// Replays are deterministic because the snapshots include the random number generator.
+struct Timeline {
+    checkpoints: Vec<Checkpoint>,
+}
+
+impl Timeline {
+    fn new() -> Self {
+        Timeline {
+            checkpoints: vec![],
+        }
+    }
+
+    /// Forgets all checkpoints, starting over from the current state.
+    fn reset(&mut self, execution_state: &IDEExecution) {
+        self.checkpoints.clear();
+        self.checkpoints.push(Checkpoint::new(execution_state));
+    }
+
+    /// Takes a checkpoint if the execution has reached the next snapshot interval.
+    fn record(&mut self, execution_state: &IDEExecution) {
+        let latest = self
+            .checkpoints
+            .last()
+            .map_or(0, |c| c.snapshot.instruction_count);
+        let count = execution_state.instruction_count;
+        if count.is_multiple_of(SNAPSHOT_INTERVAL) && count > latest {
+            self.checkpoints.push(Checkpoint::new(execution_state));
+        }
+    }
+
+    /// Moves the execution to just before instruction number `target`,
+    /// or to where it terminates if that is sooner.
+    /// Seeking stops early if the program needs input that hasn't been typed yet,
+    /// and seeking forward also stops if the pointer lands on one of the `breakpoints`.
+    fn seek(
+        &mut self,
+        execution_state: &mut IDEExecution,
+        target: u64,
+        breakpoints: &BTreeSet<Position>,
+    ) -> Result<(), ExecutionError> {
+        let forward = target > execution_state.instruction_count;
+        if target < execution_state.instruction_count {
+            self.checkpoints
+                .iter()
+                .rev()
+                .find(|c| c.snapshot.instruction_count <= target)
+                .cloned()
+                .expect("the timeline always has a checkpoint of the start of execution")
+                .restore(execution_state);
+        }
+
+        while execution_state.instruction_count < target && !execution_state.terminated {
+            if waiting_for_input(execution_state) {
+                break;
+            }
+            execution_state.step()?;
+            self.record(execution_state);
+            if forward && breakpoints.contains(&execution_state.pointer.position) {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// How many steps the `b` key can undo.
+const HISTORY_LIMIT: usize = 1_000;
+
+/// A step that the `b` key can undo,
+/// along with how much output the program had written and how much input it had read before it.
+struct UndoableStep {
+    undo: StepUndo,
+    output_len: usize,
+    input_position: usize,
+}
+
+/// The steps taken forward, so that the `b` key can undo them one at a time.
+/// Only what each step changed is kept, not a copy of the whole program.
+struct History {
+    steps: VecDeque<UndoableStep>,
+}
+
+impl History {
+    fn new() -> Self {
+        History {
+            steps: VecDeque::new(),
+        }
+    }
+
+    fn clear(&mut self) {
+        self.steps.clear();
+    }
+
+    fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    /// Remembers a step, forgetting the oldest step if the history is full.
+    fn push(&mut self, step: UndoableStep) {
+        if self.steps.len() == HISTORY_LIMIT {
+            self.steps.pop_front();
+        }
+        self.steps.push_back(step);
+    }
+
+    /// Returns the execution to the state before the most recent step, if there is one.
+    fn undo(&mut self, execution_state: &mut IDEExecution) {
+        if let Some(step) = self.steps.pop_back() {
+            execution_state.undo_step(step.undo);
+            execution_state.output.truncate(step.output_len);
+            execution_state.input.position = step.input_position;
+        }
+    }
+}
+
 struct IDEState {
     instructions_per_second: usize,
     paused: bool,
     following: bool,
     editing: bool,
+    show_branches: bool,
+    /// Whether to highlight cells that differ from the program as loaded or edited.
+    show_modified: bool,
+    numeric: bool,
+    numeric_input: String,
     view_center: Position,
     error: Option<ExecutionError>,
+    export_path: Option<String>,
+    /// The file the program was loaded from, which `s` saves it to.
+    source_path: Option<PathBuf>,
+    entering_input: bool,
+    /// The note being typed for the cell at the view center, if any.
+    annotation: Option<String>,
+    notes: Notes,
+    /// Cells that pause a running program when the pointer lands on them.
+    breakpoints: BTreeSet<Position>,
+    /// Pauses a running program once it has run this many instructions.
+    step_limit: Option<u64>,
+    message: Option<String>,
+    timeline: Timeline,
+    history: History,
 }
 
 impl IDEState {
@@ -68,8 +405,30 @@ impl IDEState {
             paused: true,
             following: false,
             editing: false,
+            show_branches: false,
+            show_modified: true,
+            numeric: false,
+            numeric_input: String::new(),
             view_center: Position { x: 0, y: 0 },
             error: None,
+            export_path: None,
+            source_path: None,
+            entering_input: false,
+            annotation: None,
+            notes: Notes::default(),
+            breakpoints: BTreeSet::new(),
+            step_limit: None,
+            message: None,
+            timeline: Timeline::new(),
+            history: History::new(),
+        }
+    }
+
+    fn debugging() -> Self {
+        IDEState {
+            paused: true,
+            following: true,
+            ..IDEState::new()
         }
     }
 
@@ -78,17 +437,26 @@ impl IDEState {
     }
 }
 
-fn run_ide<B: Backend>(terminal: &mut Terminal<B>, mut program: Program) -> io::Result<()> {
-    let input = Vec::new();
-    let output = Vec::new();
-    let mut execution_state = ExecutionState::new(program.clone(), false, input.as_slice(), output);
+fn run_ide<B: Backend>(
+    terminal: &mut Terminal<B>,
+    mut program: Program,
+    path: &Path,
+    mut ide_state: IDEState,
+) -> io::Result<()> {
+    let mut execution_state =
+        ExecutionState::new(program.clone(), false, InputBuffer::new(), Vec::new());
 
     let mut last_tick = Instant::now();
 
-    let mut ide_state = IDEState::new();
+    ide_state.timeline.reset(&execution_state);
+    ide_state.source_path = Some(path.to_path_buf());
+    match Notes::load(&Notes::path_for(path)) {
+        Ok(notes) => ide_state.notes = notes,
+        Err(e) => ide_state.message = Some(format!("couldn't load notes: {}", e)),
+    }
 
     loop {
-        terminal.draw(|f| ui(f, &execution_state, &ide_state))?;
+        terminal.draw(|f| ui(f, &execution_state, &program, &ide_state))?;
 
         let tick_time = ide_state.tick_time();
 
@@ -97,19 +465,37 @@ fn run_ide<B: Backend>(terminal: &mut Terminal<B>, mut program: Program) -> io::
             .unwrap_or_else(|| Duration::from_secs(0));
 
         if poll(time_to_next_tick)? {
-            if let Quit = handle_key(
-                event::read()?,
-                &mut ide_state,
-                &mut execution_state,
-                &mut program,
-            ) {
-                return Ok(());
+            match event::read()? {
+                Event::Resize(width, height) => {
+                    handle_resize(terminal, &mut ide_state, &execution_state, width, height)?;
+                    // redraw right away instead of waiting for the next tick
+                    continue;
+                }
+                Event::Mouse(MouseEvent {
+                    kind: MouseEventKind::Down(MouseButton::Left),
+                    column,
+                    row,
+                    ..
+                }) => {
+                    if let Some(position) = clicked_cell(terminal.size()?, &ide_state, column, row)
+                    {
+                        ide_state.view_center = position;
+                        ide_state.following = false;
+                    }
+                }
+                event => {
+                    if let Quit =
+                        handle_key(event, &mut ide_state, &mut execution_state, &mut program)
+                    {
+                        return Ok(());
+                    }
+                }
             }
         }
 
         // When handling input, we might not wait the whole poll() above, so check to see if we should tick.
         if last_tick.elapsed() >= tick_time {
-            if let Quit = handle_tick(&mut ide_state, &mut execution_state, &program) {
+            if let Quit = handle_tick(&mut ide_state, &mut execution_state) {
                 return Ok(());
             }
             last_tick = Instant::now();
@@ -117,6 +503,22 @@ fn run_ide<B: Backend>(terminal: &mut Terminal<B>, mut program: Program) -> io::
     }
 }
 
+fn handle_resize<B: Backend>(
+    terminal: &mut Terminal<B>,
+    ide_state: &mut IDEState,
+    execution_state: &IDEExecution,
+    width: u16,
+    height: u16,
+) -> io::Result<()> {
+    terminal.resize(Rect::new(0, 0, width, height))?;
+
+    if ide_state.following {
+        ide_state.view_center = execution_state.pointer.position;
+    }
+
+    Ok(())
+}
+
 enum HandleKeyResult {
     Continue,
     Quit,
@@ -125,53 +527,207 @@ enum HandleKeyResult {
 fn handle_key(
     event: Event,
     ide_state: &mut IDEState,
-    execution_state: &mut ExecutionState<&[u8], Vec<u8>>,
+    execution_state: &mut IDEExecution,
     program: &mut Program,
 ) -> HandleKeyResult {
     if let Event::Key(key) = event {
+        if let Some(path) = &mut ide_state.export_path {
+            match key.code {
+                KeyCode::Enter => {
+                    ide_state.message = Some(match fs::write(&path, &execution_state.output) {
+                        Ok(()) => format!("saved output to {}", path),
+                        Err(e) => format!("export failed: {}", e),
+                    });
+                    ide_state.export_path = None;
+                }
+                KeyCode::Esc => ide_state.export_path = None,
+                KeyCode::Backspace => {
+                    path.pop();
+                }
+                KeyCode::Char(c) => path.push(c),
+                _ => {}
+            }
+
+            return Continue;
+        }
+
+        if let Some(note) = &mut ide_state.annotation {
+            match key.code {
+                KeyCode::Enter => {
+                    ide_state.notes.set(ide_state.view_center, note);
+                    ide_state.annotation = None;
+                    if let Some(path) = &ide_state.source_path {
+                        let path = Notes::path_for(path);
+                        ide_state.message = Some(match ide_state.notes.save(&path) {
+                            Ok(()) => format!("saved notes to {}", path.display()),
+                            Err(e) => format!("saving notes failed: {}", e),
+                        });
+                    }
+                }
+                KeyCode::Esc => ide_state.annotation = None,
+                KeyCode::Backspace => {
+                    note.pop();
+                }
+                KeyCode::Char(c) => note.push(c),
+                _ => {}
+            }
+
+            return Continue;
+        }
+
+        if ide_state.entering_input {
+            let input = &mut execution_state.input;
+            match key.code {
+                KeyCode::Enter => input.bytes.push(b'\n'),
+                KeyCode::Esc => ide_state.entering_input = false,
+                KeyCode::Backspace => {
+                    // input that has already been read can't be taken back
+                    if input.bytes.len() > input.position {
+                        input.bytes.pop();
+                    }
+                }
+                KeyCode::Char(c) => {
+                    let mut utf8 = [0; 4];
+                    input
+                        .bytes
+                        .extend_from_slice(c.encode_utf8(&mut utf8).as_bytes());
+                }
+                _ => {}
+            }
+
+            return Continue;
+        }
+
         match key.code {
             KeyCode::Char('i') if !ide_state.editing => {
                 ide_state.paused = true;
                 ide_state.editing = true;
 
-                execution_state.reset();
-                execution_state.program = program.clone();
-                execution_state.output.clear();
+                restart(ide_state, execution_state, program);
             }
             KeyCode::Esc if ide_state.editing => {
                 ide_state.editing = false;
+                ide_state.numeric_input.clear();
+            }
+            KeyCode::Char(c) if ide_state.editing && ide_state.numeric => {
+                if c.is_ascii_hexdigit() {
+                    ide_state.numeric_input.push(c);
+                }
+                if ide_state.numeric_input.len() == 2 {
+                    let value = u8::from_str_radix(&ide_state.numeric_input, 16).unwrap();
+                    edit_cell(ide_state, execution_state, program, char::from(value));
+                    ide_state.view_center = ide_state.view_center.shifted(1, 0);
+                    ide_state.numeric_input.clear();
+                }
             }
             KeyCode::Char(c) if ide_state.editing => {
-                program.set(&ide_state.view_center, c);
-                execution_state.program = program.clone();
+                edit_cell(ide_state, execution_state, program, c);
+                ide_state.view_center = ide_state.view_center.shifted(1, 0);
+            }
+            KeyCode::Enter if ide_state.editing => {
+                // like a text editor, go to the start of the next row
+                let left = program.extent().map_or(0, |(upper_left, _)| upper_left.x);
+                ide_state.view_center = Position {
+                    x: left,
+                    y: ide_state.view_center.y + 1,
+                };
+            }
+            KeyCode::Backspace if ide_state.editing => {
+                ide_state.view_center = ide_state.view_center.shifted(-1, 0);
+                edit_cell(ide_state, execution_state, program, ' ');
             }
             KeyCode::Char('q') => {
                 return Quit;
             }
-            KeyCode::Char('r') => {
-                // TODO: figure out a cleaner way to handle "execute from scratch"
-                execution_state.reset();
-                execution_state.program = program.clone();
-                execution_state.output.clear();
-            }
+            KeyCode::Char('r') => restart(ide_state, execution_state, program),
             KeyCode::Char(' ') if !ide_state.editing => ide_state.paused = !ide_state.paused,
             KeyCode::Char('t') if !ide_state.editing => {
                 ide_state.paused = true;
-                let result = execution_state.step();
-                if let Err(e) = result {
+                ide_state.error = step_forward(ide_state, execution_state).err();
+            }
+            KeyCode::Char('b') if !ide_state.editing => {
+                ide_state.paused = true;
+                ide_state.error = None;
+                ide_state.history.undo(execution_state);
+                if ide_state.following {
+                    ide_state.view_center = execution_state.pointer.position;
+                }
+            }
+            KeyCode::Char(c @ ('[' | ']')) if !ide_state.editing => {
+                ide_state.paused = true;
+                let target = if c == '[' {
+                    execution_state
+                        .instruction_count
+                        .saturating_sub(SEEK_DISTANCE)
+                } else {
+                    execution_state.instruction_count + SEEK_DISTANCE
+                };
+                ide_state.history.clear();
+                let seek = ide_state
+                    .timeline
+                    .seek(execution_state, target, &ide_state.breakpoints);
+                if let Err(e) = seek {
+                    restart(ide_state, execution_state, program);
                     ide_state.error = Some(e);
-                    execution_state.reset();
-                    execution_state.program = program.clone();
-                    execution_state.output.clear();
+                } else if waiting_for_input(execution_state) {
+                    ide_state.message = Some(WAITING_FOR_INPUT.to_string());
+                } else if c == ']' && execution_state.instruction_count < target {
+                    ide_state.message = breakpoint_message(ide_state, execution_state);
+                }
+                if ide_state.following {
+                    ide_state.view_center = execution_state.pointer.position;
+                }
+            }
+            KeyCode::Char('x') if !ide_state.editing => {
+                let position = ide_state.view_center;
+                if !ide_state.breakpoints.remove(&position) {
+                    ide_state.breakpoints.insert(position);
                 }
             }
             KeyCode::Char('f') => ide_state.following = !ide_state.following,
+            KeyCode::Char('n') if !ide_state.editing => ide_state.numeric = !ide_state.numeric,
+            KeyCode::Char('m') if !ide_state.editing => {
+                ide_state.show_modified = !ide_state.show_modified
+            }
+            KeyCode::Char('v') if !ide_state.editing => {
+                ide_state.show_branches = !ide_state.show_branches
+            }
+            KeyCode::Char(':') if !ide_state.editing => {
+                ide_state.entering_input = true;
+                ide_state.message = None;
+            }
+            KeyCode::Char('s') if !ide_state.editing => {
+                if let Some(path) = &ide_state.source_path {
+                    ide_state.message = Some(match save_program(program, path) {
+                        Ok(()) => format!("saved program to {}", path.display()),
+                        Err(e) => format!("save failed: {}", e),
+                    });
+                }
+            }
+            KeyCode::Char('a') if !ide_state.editing => {
+                let note = ide_state.notes.get(&ide_state.view_center).unwrap_or("");
+                ide_state.annotation = Some(note.to_string());
+            }
+            KeyCode::Char('o') if !ide_state.editing => {
+                ide_state.paused = true;
+                ide_state.export_path = Some(String::new());
+            }
+            #[cfg(feature = "clipboard")]
+            KeyCode::Char('c') if !ide_state.editing => {
+                ide_state.message = Some(match copy_to_clipboard(&execution_state.output) {
+                    Ok(()) => "copied output".to_string(),
+                    Err(e) => format!("copy failed: {}", e),
+                });
+            }
             KeyCode::Char('+') => {
                 ide_state.instructions_per_second = (ide_state.instructions_per_second + 1).max(1)
             }
             KeyCode::Char('-') => {
                 ide_state.instructions_per_second = (ide_state.instructions_per_second - 1).max(1)
             }
+            KeyCode::Char(c @ '1'..='5') if !ide_state.editing => {
+                ide_state.instructions_per_second = SPEED_PRESETS[c as usize - '1' as usize]
+            }
             KeyCode::Left => {
                 ide_state.view_center = ide_state.view_center.shifted(-1, 0);
                 ide_state.following = false;
@@ -195,13 +751,98 @@ fn handle_key(
     Continue
 }
 
-fn handle_tick(
+/// Sets the cell at the view center while editing, which invalidates the execution so far.
+fn edit_cell(
     ide_state: &mut IDEState,
-    execution_state: &mut ExecutionState<&[u8], Vec<u8>>,
-    program: &Program,
-) -> HandleKeyResult {
+    execution_state: &mut IDEExecution,
+    program: &mut Program,
+    c: char,
+) {
+    program.set(&ide_state.view_center, c);
+    execution_state.program = program.clone();
+    ide_state.timeline.reset(execution_state);
+    ide_state.history.clear();
+}
+
+/// Writes the program to `path`, in the binary format if that's what the file extension says.
+/// The text format can't hold cells above or to the left of the origin,
+/// so programs with any are refused rather than saved without them.
+fn save_program(program: &Program, path: &Path) -> io::Result<()> {
+    if path.extension().is_some_and(|e| e == BINARY_EXTENSION) {
+        fs::write(path, program.to_bytes())
+    } else if program
+        .extent()
+        .is_some_and(|(upper_left, _)| upper_left.x < 0 || upper_left.y < 0)
+    {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "the program has cells above or left of the origin, which only a .{} file can hold",
+                BINARY_EXTENSION
+            ),
+        ))
+    } else {
+        fs::write(path, format!("{}\n", program))
+    }
+}
+
+/// Copies the output to the system clipboard, replacing any invalid UTF-8.
+#[cfg(feature = "clipboard")]
+fn copy_to_clipboard(output: &[u8]) -> Result<(), arboard::Error> {
+    arboard::Clipboard::new()?.set_text(String::from_utf8_lossy(output).into_owned())
+}
+
+/// Starts executing the program from scratch.
+fn restart(ide_state: &mut IDEState, execution_state: &mut IDEExecution, program: &Program) {
+    execution_state.reset();
+    execution_state.program = program.clone();
+    execution_state.output.clear();
+    execution_state.input.position = 0;
+    ide_state.timeline.reset(execution_state);
+    ide_state.history.clear();
+    ide_state.error = None;
+}
+
+/// Executes one instruction, remembering how to undo it.
+/// If the instruction fails, it is undone right away,
+/// leaving the pointer on it and the history intact.
+fn step_forward(ide_state: &mut IDEState, execution_state: &mut IDEExecution) -> ExecutionResult {
+    let terminated = execution_state.terminated;
+    let output_len = execution_state.output.len();
+    let input_position = execution_state.input.position;
+
+    let (undo, result) = execution_state.step_with_undo();
+    if !terminated {
+        ide_state.history.push(UndoableStep {
+            undo,
+            output_len,
+            input_position,
+        });
+    }
+
+    if result.is_err() {
+        ide_state.history.undo(execution_state);
+    } else {
+        ide_state.timeline.record(execution_state);
+    }
+
+    result
+}
+
+fn handle_tick(ide_state: &mut IDEState, execution_state: &mut IDEExecution) -> HandleKeyResult {
     if !ide_state.paused {
-        let result = execution_state.step();
+        if waiting_for_input(execution_state) {
+            ide_state.paused = true;
+            ide_state.message = Some(WAITING_FOR_INPUT.to_string());
+            return Continue;
+        }
+        if let Some(message) = step_limit_message(ide_state, execution_state) {
+            ide_state.paused = true;
+            ide_state.message = Some(message);
+            return Continue;
+        }
+
+        let result = step_forward(ide_state, execution_state);
 
         if ide_state.following {
             ide_state.view_center = execution_state.pointer.position
@@ -210,24 +851,142 @@ fn handle_tick(
         if let Err(e) = result {
             ide_state.paused = true;
             ide_state.error = Some(e);
-            execution_state.reset();
-            execution_state.program = program.clone();
-            execution_state.output.clear();
+        } else if let Some(message) = breakpoint_message(ide_state, execution_state)
+            .or_else(|| step_limit_message(ide_state, execution_state))
+        {
+            ide_state.paused = true;
+            ide_state.message = Some(message);
         }
     }
 
     Continue
 }
 
-fn ui<B: Backend>(
-    f: &mut Frame<B>,
-    program_state: &ExecutionState<&[u8], Vec<u8>>,
-    ide_state: &IDEState,
-) {
+/// What the IDE says when the program is `waiting_for_input`.
+const WAITING_FOR_INPUT: &str = "waiting for input (press :)";
+
+/// Whether the next instruction reads input that hasn't been typed yet.
+/// Stepping now would read the end of the input instead of waiting for more.
+fn waiting_for_input(execution_state: &IDEExecution) -> bool {
+    execution_state.wants_input() && execution_state.input.unread().is_empty()
+}
+
+/// A message to show if the program has run as many instructions as the step limit allows.
+fn step_limit_message(ide_state: &IDEState, execution_state: &IDEExecution) -> Option<String> {
+    ide_state
+        .step_limit
+        .filter(|&limit| !execution_state.terminated && execution_state.instruction_count >= limit)
+        .map(|limit| format!("step limit of {} reached", limit))
+}
+
+/// A message to show if the pointer has landed on a breakpoint.
+fn breakpoint_message(ide_state: &IDEState, execution_state: &IDEExecution) -> Option<String> {
+    let position = execution_state.pointer.position;
+    (!execution_state.terminated && ide_state.breakpoints.contains(&position))
+        .then(|| format!("breakpoint at ({}, {})", position.x, position.y))
+}
+
+/// If the pointer is on a branching instruction, the cells it could move to next,
+/// each paired with whether the current top of the stack sends the pointer there.
+/// `?` picks randomly, so all four of its neighbors are possible.
+fn branch_targets(execution_state: &IDEExecution) -> Vec<(Position, bool)> {
+    if execution_state.terminated || execution_state.in_string_mode() {
+        return vec![];
+    }
+
+    let pos = execution_state.pointer.position;
+    let top_is_zero = execution_state.stack.peek() == 0;
+
+    match execution_state
+        .program
+        .get(&execution_state.pointer.position)
+    {
+        '_' => vec![
+            (pos.shifted(1, 0), top_is_zero),
+            (pos.shifted(-1, 0), !top_is_zero),
+        ],
+        '|' => vec![
+            (pos.shifted(0, 1), top_is_zero),
+            (pos.shifted(0, -1), !top_is_zero),
+        ],
+        '?' => vec![
+            (pos.shifted(1, 0), true),
+            (pos.shifted(-1, 0), true),
+            (pos.shifted(0, 1), true),
+            (pos.shifted(0, -1), true),
+        ],
+        _ => vec![],
+    }
+}
+
+/// Renders a program cell as the two-digit hexadecimal value that `g` would push for it
+/// (its codepoint, truncated to a byte).
+/// Typing two hex digits while editing in numeric mode stores the corresponding byte as a char.
+fn cell_value_text(c: char) -> String {
+    format!("{:02x}", c as u32 as u8)
+}
+
+/// Renders a program cell, replacing control characters (which `p` can write)
+/// with a placeholder so that they cannot break the grid layout.
+fn cell_text(c: char) -> String {
+    if c.is_control() {
+        '·'.to_string()
+    } else {
+        c.to_string()
+    }
+}
+
+/// Lists the stack's items newest first, fitting them into `rows` lines
+/// by replacing the oldest items with a count of how many are hidden.
+fn stack_lines(items: &[isize], rows: usize) -> Vec<String> {
+    let newest_first = items.iter().rev().map(|i| i.to_string());
+
+    if items.len() <= rows {
+        return newest_first.collect();
+    }
+
+    let shown = rows.saturating_sub(1);
+    let mut lines = newest_first.take(shown).collect_vec();
+    lines.push(format!("… +{} more", items.len() - shown));
+    lines
+}
+
+/// Describes the instruction count and the instruction and direction of the pointer.
+fn pointer_text(execution_state: &IDEExecution) -> String {
+    let pointer = execution_state.pointer;
+    let direction = match pointer.direction() {
+        Some(PointerDirection::Up) => "facing up".to_string(),
+        Some(PointerDirection::Down) => "facing down".to_string(),
+        Some(PointerDirection::Left) => "facing left".to_string(),
+        Some(PointerDirection::Right) => "facing right".to_string(),
+        None => format!("moving ({}, {})", pointer.delta.0, pointer.delta.1),
+    };
+    format!(
+        "step {} on '{}' {}",
+        execution_state.instruction_count,
+        cell_text(
+            execution_state
+                .program
+                .get(&execution_state.pointer.position)
+        ),
+        direction
+    )
+}
+
+/// The areas of the screen that the IDE's panels are drawn in.
+struct Areas {
+    program: Rect,
+    stack: Rect,
+    output: Rect,
+    input: Rect,
+    state: Rect,
+}
+
+fn areas(size: Rect) -> Areas {
     let chunks = Layout::default()
         .direction(Direction::Vertical)
         .constraints([Constraint::Percentage(80), Constraint::Percentage(20)].as_ref())
-        .split(f.size());
+        .split(size);
     let upper = chunks[0];
     let lower = chunks[1];
 
@@ -235,17 +994,40 @@ fn ui<B: Backend>(
         .direction(Direction::Horizontal)
         .constraints([Constraint::Percentage(90), Constraint::Percentage(10)].as_ref())
         .split(upper);
-    let program_area = upper_chunks[0];
-    let stack_area = upper_chunks[1];
 
     let lower_chunks = Layout::default()
         .direction(Direction::Horizontal)
-        .constraints([Constraint::Percentage(90), Constraint::Percentage(10)].as_ref())
+        .constraints(
+            [
+                Constraint::Percentage(60),
+                Constraint::Percentage(30),
+                Constraint::Percentage(10),
+            ]
+            .as_ref(),
+        )
         .split(lower);
-    let output_area = lower_chunks[0];
-    let state_area = lower_chunks[1];
 
-    let w = program_area.width as isize;
+    Areas {
+        program: upper_chunks[0],
+        stack: upper_chunks[1],
+        output: lower_chunks[0],
+        input: lower_chunks[1],
+        state: lower_chunks[2],
+    }
+}
+
+/// How many columns each program cell takes up.
+fn cell_width(ide_state: &IDEState) -> isize {
+    if ide_state.numeric {
+        3
+    } else {
+        1
+    }
+}
+
+/// The positions of the cells in the upper-left and lower-right corners of the program area.
+fn view_bounds(program_area: Rect, ide_state: &IDEState) -> (Position, Position) {
+    let w = program_area.width as isize / cell_width(ide_state);
     let h = program_area.height as isize;
 
     let upper_left = Position {
@@ -257,7 +1039,67 @@ fn ui<B: Backend>(
         y: upper_left.y + h,
     };
 
-    let widths = vec![Constraint::Length(1); w as usize];
+    (upper_left, lower_right)
+}
+
+/// The program cell drawn at a terminal column and row, if any.
+fn clicked_cell(size: Rect, ide_state: &IDEState, column: u16, row: u16) -> Option<Position> {
+    let program_area = areas(size).program;
+    // the cells are drawn inside the panel's border
+    let inner = Rect::new(
+        program_area.x + 1,
+        program_area.y + 1,
+        program_area.width.saturating_sub(2),
+        program_area.height.saturating_sub(2),
+    );
+    if column < inner.x
+        || column >= inner.x + inner.width
+        || row < inner.y
+        || row >= inner.y + inner.height
+    {
+        return None;
+    }
+
+    let (upper_left, _) = view_bounds(program_area, ide_state);
+    Some(upper_left.shifted(
+        (column - inner.x) as isize / cell_width(ide_state),
+        (row - inner.y) as isize,
+    ))
+}
+
+/// Draws the IDE. `original` is the program before execution started,
+/// which cells changed by `p` are highlighted against.
+fn ui<B: Backend>(
+    f: &mut Frame<B>,
+    program_state: &IDEExecution,
+    original: &Program,
+    ide_state: &IDEState,
+) {
+    let Areas {
+        program: program_area,
+        stack: stack_area,
+        output: output_area,
+        input: input_area,
+        state: state_area,
+    } = areas(f.size());
+
+    let cell_width = cell_width(ide_state);
+    let w = program_area.width as isize / cell_width;
+    let (upper_left, lower_right) = view_bounds(program_area, ide_state);
+
+    let widths = vec![Constraint::Length(cell_width as u16); w as usize];
+
+    let branches = if ide_state.show_branches {
+        branch_targets(program_state)
+    } else {
+        vec![]
+    };
+
+    let modified = if ide_state.show_modified {
+        program_state.program.diff(original)
+    } else {
+        vec![]
+    };
 
     let program_grid = Table::new(
         program_state
@@ -273,20 +1115,51 @@ fn ui<B: Backend>(
                         } else {
                             Style::default().bg(Color::Green)
                         }
+                    } else if program_state
+                        .threads()
+                        .iter()
+                        .any(|thread| thread.pointer.position == p)
+                    {
+                        Style::default().bg(Color::Cyan)
                     } else if p == ide_state.view_center {
                         Style::default().bg(Color::LightMagenta)
+                    } else if ide_state.breakpoints.contains(&p) {
+                        Style::default().bg(Color::Blue)
+                    } else if let Some((_, taken)) = branches.iter().find(|(b, _)| *b == p) {
+                        if *taken {
+                            Style::default().bg(Color::Yellow)
+                        } else {
+                            Style::default().bg(Color::DarkGray)
+                        }
                     } else {
                         Style::default()
                     };
-                    Cell::from(c.to_string()).style(style)
+                    let style = if modified.iter().any(|(m, _, _)| *m == p) {
+                        style.fg(Color::LightRed)
+                    } else {
+                        style
+                    };
+                    let style = if ide_state.notes.get(&p).is_some() {
+                        style.add_modifier(Modifier::UNDERLINED)
+                    } else {
+                        style
+                    };
+                    let text = if ide_state.numeric {
+                        cell_value_text(c)
+                    } else {
+                        cell_text(c)
+                    };
+                    Cell::from(text).style(style)
                 }))
             }),
     )
     .block(
         Block::default()
             .title(format!(
-                " Program | (x, y) = ({}, {}) ",
-                ide_state.view_center.x, ide_state.view_center.y
+                " Program | (x, y) = ({}, {}) | {} ",
+                ide_state.view_center.x,
+                ide_state.view_center.y,
+                pointer_text(program_state)
             ))
             .title_alignment(Alignment::Center)
             .borders(Borders::ALL),
@@ -296,16 +1169,17 @@ fn ui<B: Backend>(
     .column_spacing(0);
 
     let stack = List::new(
-        program_state
-            .stack
-            .items()
-            .iter()
-            .map(|i| ListItem::new(i.to_string()))
-            .collect_vec(),
+        stack_lines(
+            &program_state.stack.items(),
+            stack_area.height.saturating_sub(2) as usize,
+        )
+        .into_iter()
+        .map(ListItem::new)
+        .collect_vec(),
     )
     .block(
         Block::default()
-            .title(" Stack ")
+            .title(format!(" Stack ({}) ", program_state.stack.depth()))
             .title_alignment(Alignment::Center)
             .borders(Borders::ALL),
     )
@@ -328,16 +1202,86 @@ fn ui<B: Backend>(
         .alignment(Alignment::Left)
         .wrap(Wrap { trim: false });
 
-    let mut settings = vec![];
+    let input = Paragraph::new(String::from_utf8_lossy(program_state.input.unread()).into_owned())
+        .block(
+            Block::default()
+                .title(
+                    if ide_state.entering_input {
+                        " Input (typing) "
+                    } else {
+                        " Input "
+                    },
+                )
+                .title_alignment(Alignment::Center)
+                .borders(Borders::ALL),
+        )
+        .style(Style::default().fg(Color::White).bg(Color::Black))
+        .alignment(Alignment::Left)
+        .wrap(Wrap { trim: false });
+
+    let mut settings = vec![ListItem::new(format!(
+        "{} ips",
+        ide_state.instructions_per_second
+    ))];
+    if let Some((position, instruction)) = program_state.halted_on() {
+        settings.push(ListItem::new(format!(
+            "halted at ({}, {}) on {}",
+            position.x, position.y, instruction
+        )));
+    }
+    if let Some(termination) = program_state.termination {
+        settings.push(ListItem::new(termination.to_string()));
+    }
     if ide_state.editing {
         settings.push(ListItem::new("editing"));
     }
     if ide_state.paused {
         settings.push(ListItem::new("paused"));
     }
+    if !program_state.threads().is_empty() {
+        settings.push(ListItem::new(format!(
+            "{} other pointers",
+            program_state.threads().len()
+        )));
+    }
+    if ide_state.history.len() > 0 {
+        settings.push(ListItem::new(format!(
+            "history {}",
+            ide_state.history.len()
+        )));
+    }
+    if !ide_state.breakpoints.is_empty() {
+        settings.push(ListItem::new(format!(
+            "breakpoints {}",
+            ide_state.breakpoints.len()
+        )));
+    }
     if ide_state.following {
         settings.push(ListItem::new("following"));
     }
+    if ide_state.show_branches {
+        settings.push(ListItem::new("branches"));
+    }
+    if ide_state.show_modified {
+        settings.push(ListItem::new(format!("modified {}", modified.len())));
+    }
+    if ide_state.numeric {
+        settings.push(ListItem::new(format!(
+            "numeric {}",
+            ide_state.numeric_input
+        )));
+    }
+    if let Some(path) = &ide_state.export_path {
+        settings.push(ListItem::new(format!("export to: {}", path)));
+    }
+    if let Some(note) = &ide_state.annotation {
+        settings.push(ListItem::new(format!("note (typing): {}", note)));
+    } else if let Some(note) = ide_state.notes.get(&ide_state.view_center) {
+        settings.push(ListItem::new(format!("note: {}", note)));
+    }
+    if let Some(message) = &ide_state.message {
+        settings.push(ListItem::new(message.as_str()));
+    }
     let state = List::new(settings)
         .block(
             Block::default()
@@ -350,5 +1294,808 @@ fn ui<B: Backend>(
     f.render_widget(program_grid, program_area);
     f.render_widget(stack, stack_area);
     f.render_widget(output, output_area);
-    f.render_widget(state, state_area);
+    f.render_widget(input, input_area);
+    if let Some(limit) = ide_state.step_limit {
+        let state_chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Min(0), Constraint::Length(3)].as_ref())
+            .split(state_area);
+        let count = program_state.instruction_count;
+        let progress = Gauge::default()
+            .block(Block::default().borders(Borders::ALL))
+            .gauge_style(Style::default().fg(Color::Green).bg(Color::Black))
+            .ratio((count as f64 / limit.max(1) as f64).min(1.0))
+            .label(format!("{} / {}", count, limit));
+        f.render_widget(state, state_chunks[0]);
+        f.render_widget(progress, state_chunks[1]);
+    } else {
+        f.render_widget(state, state_area);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeSet, str::FromStr};
+
+    use crossterm::event::{Event, KeyCode, KeyEvent};
+    use tui::{backend::TestBackend, layout::Rect, style::Color, Terminal};
+
+    use crate::{
+        examples::{ERATOSTHENES, INPUT},
+        execution::{ExecutionError, ExecutionState},
+        ide::{
+            branch_targets, cell_value_text, clicked_cell, handle_key, handle_resize, handle_tick,
+            pointer_text, stack_lines, step_forward, ui, IDEExecution, IDEState, InputBuffer,
+            Notes, TerminalGuard, Timeline,
+        },
+        program::{Position, Program},
+    };
+
+    type GenericResult = Result<(), Box<dyn std::error::Error>>;
+
+    #[test]
+    fn control_characters_render_as_placeholders() -> GenericResult {
+        // write a newline into the cell at (0, 0)
+        let program = Program::from_str("25*00p@")?;
+        let mut execution =
+            ExecutionState::new(program.clone(), false, InputBuffer::new(), Vec::new());
+        execution.run()?;
+
+        let mut ide_state = IDEState::new();
+        // put the view center far away so that (0, 0) is not highlighted
+        ide_state.view_center.x = 10;
+        ide_state.view_center.y = 5;
+
+        let mut terminal = Terminal::new(TestBackend::new(40, 20))?;
+        terminal.draw(|f| ui(f, &execution, &program, &ide_state))?;
+
+        let buffer = terminal.backend().buffer();
+        let rows = (0..buffer.area.height)
+            .map(|y| {
+                (0..buffer.area.width)
+                    .map(|x| buffer.get(x, y).symbol.as_str())
+                    .collect::<String>()
+            })
+            .collect::<Vec<_>>();
+        assert!(
+            rows.iter().any(|row| row.contains("·5*00p@")),
+            "{:#?}",
+            rows
+        );
+        assert!(buffer
+            .content
+            .iter()
+            .all(|cell| !cell.symbol.contains('\n')));
+
+        Ok(())
+    }
+
+    #[test]
+    fn branch_targets_follow_the_top_of_the_stack() -> GenericResult {
+        let program = Program::from_str("1_")?;
+        let mut execution = ExecutionState::new(program, false, InputBuffer::new(), Vec::new());
+
+        assert!(branch_targets(&execution).is_empty());
+
+        execution.step()?;
+        assert_eq!(
+            branch_targets(&execution),
+            vec![
+                (Position { x: 2, y: 0 }, false),
+                (Position { x: 0, y: 0 }, true)
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn cell_values_render_as_hex() {
+        assert_eq!(cell_value_text('@'), "40");
+        assert_eq!(cell_value_text('\n'), "0a");
+    }
+
+    #[test]
+    fn resize_redraws_at_the_new_size() -> GenericResult {
+        let program = Program::from_str("1>")?;
+        let mut execution =
+            ExecutionState::new(program.clone(), false, InputBuffer::new(), Vec::new());
+        execution.step()?;
+
+        let mut ide_state = IDEState::new();
+        ide_state.following = true;
+
+        let mut terminal = Terminal::new(TestBackend::new(40, 20))?;
+        terminal.draw(|f| ui(f, &execution, &program, &ide_state))?;
+
+        terminal.backend_mut().resize(10, 5);
+        handle_resize(&mut terminal, &mut ide_state, &execution, 10, 5)?;
+        terminal.draw(|f| ui(f, &execution, &program, &ide_state))?;
+
+        assert_eq!(terminal.backend().buffer().area.width, 10);
+        assert_eq!(terminal.backend().buffer().area.height, 5);
+        assert_eq!(ide_state.view_center, Position { x: 1, y: 0 });
+
+        Ok(())
+    }
+
+    #[test]
+    fn timeline_seeks_forward_and_backward() -> GenericResult {
+        let program = Program::from_str(ERATOSTHENES)?;
+
+        let mut expected =
+            ExecutionState::new(program.clone(), false, InputBuffer::new(), Vec::new());
+        for _ in 0..123 {
+            expected.step()?;
+        }
+
+        let mut execution = ExecutionState::new(program, false, InputBuffer::new(), Vec::new());
+        let mut timeline = Timeline::new();
+        timeline.reset(&execution);
+
+        timeline.seek(&mut execution, 456, &BTreeSet::new())?;
+        assert_eq!(execution.instruction_count, 456);
+
+        timeline.seek(&mut execution, 123, &BTreeSet::new())?;
+        assert_eq!(execution.instruction_count, 123);
+        assert_eq!(execution.pointer, expected.pointer);
+        assert_eq!(execution.stack.items(), expected.stack.items());
+        assert_eq!(execution.output, expected.output);
+
+        timeline.seek(&mut execution, 1_000_000, &BTreeSet::new())?;
+        assert!(execution.terminated);
+        assert_eq!(
+            String::from_utf8(execution.output).unwrap(),
+            "2 3 5 7 11 13 17 19 23 29 31 37 41 43 47 53 59 61 67 71 73 79 "
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn history_undoes_steps() -> GenericResult {
+        let program = Program::from_str("1.2.3.@")?;
+        let mut execution = ExecutionState::new(program, false, InputBuffer::new(), Vec::new());
+        let mut ide_state = IDEState::new();
+        ide_state.timeline.reset(&execution);
+
+        // undoing with no history does nothing
+        ide_state.history.undo(&mut execution);
+        assert_eq!(execution.instruction_count, 0);
+
+        for _ in 0..4 {
+            step_forward(&mut ide_state, &mut execution)?;
+        }
+        assert_eq!(execution.output, b"1 2 ");
+        assert_eq!(ide_state.history.len(), 4);
+
+        ide_state.history.undo(&mut execution);
+        assert_eq!(execution.instruction_count, 3);
+        assert_eq!(execution.output, b"1 ");
+        assert_eq!(execution.stack.items(), vec![2]);
+        assert_eq!(ide_state.history.len(), 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn input_panel_feeds_the_program() -> GenericResult {
+        let mut program = Program::from_str(INPUT)?;
+        let mut execution =
+            ExecutionState::new(program.clone(), false, InputBuffer::new(), Vec::new());
+        let mut ide_state = IDEState::new();
+        ide_state.timeline.reset(&execution);
+        ide_state.paused = false;
+
+        // with nothing typed, the program pauses instead of reading the end of the input
+        handle_tick(&mut ide_state, &mut execution);
+        assert!(ide_state.paused);
+        assert_eq!(execution.instruction_count, 0);
+
+        let keys = [
+            KeyCode::Char(':'),
+            KeyCode::Char('4'),
+            KeyCode::Char('2'),
+            KeyCode::Enter,
+            KeyCode::Char('x'),
+            KeyCode::Char('y'),
+            KeyCode::Backspace,
+            KeyCode::Esc,
+        ];
+        for key in keys {
+            handle_key(
+                Event::Key(KeyEvent::from(key)),
+                &mut ide_state,
+                &mut execution,
+                &mut program,
+            );
+        }
+        assert!(!ide_state.entering_input);
+        assert_eq!(execution.input.unread(), b"42\nx");
+
+        ide_state.paused = false;
+        for _ in 0..5 {
+            handle_tick(&mut ide_state, &mut execution);
+        }
+        assert!(execution.terminated);
+        assert_eq!(String::from_utf8(execution.output.clone())?, "42 10 ");
+
+        // rewinding also rewinds the input, including the newline that & read ahead
+        for _ in 0..4 {
+            ide_state.history.undo(&mut execution);
+        }
+        assert_eq!(execution.instruction_count, 1);
+        assert_eq!(execution.input.unread(), b"x");
+        ide_state.history.undo(&mut execution);
+        assert_eq!(execution.input.unread(), b"42\nx");
+
+        Ok(())
+    }
+
+    #[test]
+    fn step_limit_pauses_and_shows_progress() -> GenericResult {
+        let program = Program::from_str(">v\n^<")?;
+        let mut execution =
+            ExecutionState::new(program.clone(), false, InputBuffer::new(), Vec::new());
+        let mut ide_state = IDEState::new();
+        ide_state.timeline.reset(&execution);
+        ide_state.step_limit = Some(5);
+        ide_state.paused = false;
+
+        for _ in 0..10 {
+            handle_tick(&mut ide_state, &mut execution);
+        }
+        assert!(ide_state.paused);
+        assert_eq!(execution.instruction_count, 5);
+        assert_eq!(
+            ide_state.message.as_deref(),
+            Some("step limit of 5 reached")
+        );
+
+        // resuming doesn't run past the limit
+        ide_state.paused = false;
+        handle_tick(&mut ide_state, &mut execution);
+        assert!(ide_state.paused);
+        assert_eq!(execution.instruction_count, 5);
+
+        let mut terminal = Terminal::new(TestBackend::new(80, 30))?;
+        terminal.draw(|f| ui(f, &execution, &program, &ide_state))?;
+        let buffer = terminal.backend().buffer();
+        let screen = (0..buffer.area.height)
+            .flat_map(|y| (0..buffer.area.width).map(move |x| (x, y)))
+            .map(|(x, y)| buffer.get(x, y).symbol.as_str())
+            .collect::<String>();
+        assert!(screen.contains("5 / 5"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn save_writes_the_edited_program() -> GenericResult {
+        let path = std::env::temp_dir().join(format!("fungoid-{}-save.bf", std::process::id()));
+        let mut program = Program::from_str("1.@")?;
+        let mut execution =
+            ExecutionState::new(program.clone(), false, InputBuffer::new(), Vec::new());
+        let mut ide_state = IDEState::new();
+        ide_state.source_path = Some(path.clone());
+
+        for key in [
+            KeyCode::Char('i'),
+            KeyCode::Char('2'),
+            KeyCode::Esc,
+            KeyCode::Char('s'),
+        ] {
+            handle_key(
+                Event::Key(KeyEvent::from(key)),
+                &mut ide_state,
+                &mut execution,
+                &mut program,
+            );
+        }
+
+        let saved = std::fs::read_to_string(&path);
+        std::fs::remove_file(&path)?;
+        assert_eq!(saved?, "2.@\n");
+        assert_eq!(
+            ide_state.message,
+            Some(format!("saved program to {}", path.display()))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn save_refuses_cells_the_text_format_would_drop() -> GenericResult {
+        let path =
+            std::env::temp_dir().join(format!("fungoid-{}-save-negative.bf", std::process::id()));
+        let mut program = Program::from_str("1.@")?;
+        program.set(&Position { x: -1, y: 0 }, '>');
+        let mut execution =
+            ExecutionState::new(program.clone(), false, InputBuffer::new(), Vec::new());
+        let mut ide_state = IDEState::new();
+        ide_state.source_path = Some(path.clone());
+
+        handle_key(
+            Event::Key(KeyEvent::from(KeyCode::Char('s'))),
+            &mut ide_state,
+            &mut execution,
+            &mut program,
+        );
+
+        assert!(!path.exists());
+        assert_eq!(
+            ide_state.message,
+            Some(
+                "save failed: the program has cells above or left of the origin, which only a .bfz file can hold"
+                    .to_string()
+            )
+        );
+
+        // the binary format keeps them
+        let path = path.with_extension("bfz");
+        ide_state.source_path = Some(path.clone());
+        handle_key(
+            Event::Key(KeyEvent::from(KeyCode::Char('s'))),
+            &mut ide_state,
+            &mut execution,
+            &mut program,
+        );
+
+        let saved = std::fs::read(&path);
+        std::fs::remove_file(&path)?;
+        assert_eq!(Program::from_bytes(&saved?)?, program);
+
+        Ok(())
+    }
+
+    #[test]
+    fn save_failure_is_reported() -> GenericResult {
+        let mut program = Program::from_str("@")?;
+        let mut execution =
+            ExecutionState::new(program.clone(), false, InputBuffer::new(), Vec::new());
+        let mut ide_state = IDEState::new();
+        ide_state.source_path = Some(
+            std::env::temp_dir()
+                .join("fungoid-missing-dir")
+                .join("x.bf"),
+        );
+
+        handle_key(
+            Event::Key(KeyEvent::from(KeyCode::Char('s'))),
+            &mut ide_state,
+            &mut execution,
+            &mut program,
+        );
+
+        assert!(ide_state
+            .message
+            .as_deref()
+            .is_some_and(|m| m.starts_with("save failed:")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn program_title_shows_the_pointer() -> GenericResult {
+        let program = Program::from_str("12v")?;
+        let mut execution =
+            ExecutionState::new(program.clone(), false, InputBuffer::new(), Vec::new());
+        assert_eq!(pointer_text(&execution), "step 0 on '1' facing right");
+
+        execution.step()?;
+        execution.step()?;
+        execution.step()?;
+        assert_eq!(pointer_text(&execution), "step 3 on ' ' facing down");
+
+        let mut terminal = Terminal::new(TestBackend::new(100, 20))?;
+        terminal.draw(|f| ui(f, &execution, &program, &IDEState::new()))?;
+        let buffer = terminal.backend().buffer();
+        let title = (0..buffer.area.width)
+            .map(|x| buffer.get(x, 0).symbol.as_str())
+            .collect::<String>();
+        assert!(title.contains("step 3 on ' ' facing down"), "{}", title);
+
+        Ok(())
+    }
+
+    #[test]
+    fn clicks_select_program_cells() {
+        let size = Rect::new(0, 0, 40, 20);
+        let mut ide_state = IDEState::new();
+        ide_state.view_center = Position { x: 10, y: 5 };
+
+        // the program area is 36 columns by 16 rows, so (10, 5) is at the center of that
+        assert_eq!(
+            clicked_cell(size, &ide_state, 1 + 18, 1 + 8),
+            Some(Position { x: 10, y: 5 })
+        );
+        assert_eq!(
+            clicked_cell(size, &ide_state, 1, 1),
+            Some(Position { x: -8, y: -3 })
+        );
+
+        // borders and other panels
+        assert_eq!(clicked_cell(size, &ide_state, 0, 5), None);
+        assert_eq!(clicked_cell(size, &ide_state, 5, 19), None);
+        assert_eq!(clicked_cell(size, &ide_state, 38, 5), None);
+
+        ide_state.numeric = true;
+        assert_eq!(
+            clicked_cell(size, &ide_state, 1 + 7, 1),
+            Some(Position { x: 4 + 2, y: -3 })
+        );
+    }
+
+    #[test]
+    fn debugging_starts_paused_and_following() {
+        let ide_state = IDEState::debugging();
+
+        assert!(ide_state.paused);
+        assert!(ide_state.following);
+        assert!(!ide_state.editing);
+    }
+
+    #[test]
+    fn stack_lines_show_newest_first() {
+        assert_eq!(stack_lines(&[1, 2, 3], 5), vec!["3", "2", "1"]);
+        assert_eq!(stack_lines(&[1, 2, 3], 3), vec!["3", "2", "1"]);
+        assert_eq!(
+            stack_lines(&[1, 2, 3, 4, 5, 6], 3),
+            vec!["6", "5", "… +4 more"]
+        );
+        assert_eq!(stack_lines(&[1, 2], 0), vec!["… +2 more"]);
+    }
+
+    #[test]
+    fn self_modified_cells_are_highlighted() -> GenericResult {
+        // write a $ into the cell at (0, 0)
+        let mut program = Program::from_str("66*00p@")?;
+        let mut execution =
+            ExecutionState::new(program.clone(), false, InputBuffer::new(), Vec::new());
+        execution.run()?;
+
+        let mut ide_state = IDEState::new();
+        ide_state.view_center = Position { x: 10, y: 5 };
+
+        let mut terminal = Terminal::new(TestBackend::new(40, 20))?;
+        fn dollar_colors(
+            terminal: &mut Terminal<TestBackend>,
+            execution: &IDEExecution,
+            program: &Program,
+            ide_state: &IDEState,
+        ) -> Result<Vec<Color>, std::io::Error> {
+            terminal.draw(|f| ui(f, execution, program, ide_state))?;
+            Ok(terminal
+                .backend()
+                .buffer()
+                .content
+                .iter()
+                .filter(|cell| cell.symbol == "$")
+                .map(|cell| cell.fg)
+                .collect())
+        }
+
+        assert!(ide_state.show_modified);
+        assert_eq!(
+            dollar_colors(&mut terminal, &execution, &program, &ide_state)?,
+            vec![Color::LightRed]
+        );
+
+        handle_key(
+            Event::Key(KeyEvent::from(KeyCode::Char('m'))),
+            &mut ide_state,
+            &mut execution,
+            &mut program,
+        );
+        assert!(!ide_state.show_modified);
+        assert_eq!(
+            dollar_colors(&mut terminal, &execution, &program, &ide_state)?,
+            vec![Color::White]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn editing_moves_the_cursor_like_a_text_editor() -> GenericResult {
+        let mut program = Program::from_str("@")?;
+        let mut execution =
+            ExecutionState::new(program.clone(), false, InputBuffer::new(), Vec::new());
+        let mut ide_state = IDEState::new();
+        ide_state.timeline.reset(&execution);
+
+        let keys = [
+            KeyCode::Char('i'),
+            KeyCode::Char('1'),
+            KeyCode::Char('2'),
+            KeyCode::Char('v'),
+            KeyCode::Enter,
+            KeyCode::Char('x'),
+            KeyCode::Char('y'),
+            KeyCode::Backspace,
+            KeyCode::Right,
+            KeyCode::Char('<'),
+            KeyCode::Esc,
+        ];
+        for key in keys {
+            handle_key(
+                Event::Key(KeyEvent::from(key)),
+                &mut ide_state,
+                &mut execution,
+                &mut program,
+            );
+        }
+
+        assert_eq!(program.to_string(), "12v\nx <");
+        assert_eq!(execution.program, program);
+        assert_eq!(ide_state.view_center, Position { x: 3, y: 1 });
+
+        Ok(())
+    }
+
+    #[test]
+    fn terminal_guard_restores_the_terminal_when_dropped() -> GenericResult {
+        let mut out = Vec::new();
+
+        let guard = TerminalGuard::enter(&mut out, false)?;
+        drop(guard);
+
+        let written = String::from_utf8(out)?;
+        let entered = written
+            .find("\x1b[?1049h")
+            .expect("entered the alternate screen");
+        let left = written
+            .find("\x1b[?1049l")
+            .expect("left the alternate screen");
+        assert!(entered < left);
+        // the cursor is shown again
+        assert!(written[left..].contains("\x1b[?25h"), "{:?}", written);
+
+        Ok(())
+    }
+
+    #[test]
+    fn terminal_guard_restores_the_terminal_on_panic() {
+        let mut out = Vec::new();
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _guard = TerminalGuard::enter(&mut out, false).unwrap();
+            panic!("the IDE crashed");
+        }));
+
+        assert!(result.is_err());
+        assert!(String::from_utf8_lossy(&out).contains("\x1b[?1049l"));
+    }
+
+    #[test]
+    fn notes_round_trip_through_text() -> GenericResult {
+        let mut notes = Notes::default();
+        notes.set(Position { x: 3, y: 0 }, "loop counter");
+        notes.set(Position { x: -1, y: 2 }, "  tabs\tand spaces  ");
+        notes.set(Position { x: 5, y: 5 }, "removed");
+        notes.set(Position { x: 5, y: 5 }, " ");
+
+        let text = notes.to_text();
+        assert_eq!(text, "-1\t2\ttabs\tand spaces\n3\t0\tloop counter\n");
+        assert_eq!(Notes::parse(&text)?, notes);
+        assert_eq!(notes.get(&Position { x: 5, y: 5 }), None);
+
+        assert!(Notes::parse("1\tnope\tnote").is_err());
+        assert!(Notes::parse("1\t2").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn notes_are_saved_next_to_the_program() -> GenericResult {
+        let path = std::env::temp_dir().join(format!("fungoid-{}-notes.bf", std::process::id()));
+        let notes_path = Notes::path_for(&path);
+        assert_eq!(
+            notes_path.file_name().unwrap(),
+            format!("fungoid-{}-notes.bf.notes", std::process::id()).as_str()
+        );
+
+        // a program without a sidecar file has no notes
+        assert_eq!(Notes::load(&notes_path)?, Notes::default());
+
+        let mut program = Program::from_str("1.@")?;
+        let mut execution =
+            ExecutionState::new(program.clone(), false, InputBuffer::new(), Vec::new());
+        let mut ide_state = IDEState::new();
+        ide_state.source_path = Some(path);
+        ide_state.view_center = Position { x: 1, y: 0 };
+
+        let mut type_keys = |keys: &[KeyCode], ide_state: &mut IDEState| {
+            for key in keys {
+                handle_key(
+                    Event::Key(KeyEvent::from(*key)),
+                    ide_state,
+                    &mut execution,
+                    &mut program,
+                );
+            }
+        };
+
+        type_keys(
+            &[
+                KeyCode::Char('a'),
+                KeyCode::Char('o'),
+                KeyCode::Char('u'),
+                KeyCode::Char('t'),
+                KeyCode::Char('x'),
+                KeyCode::Backspace,
+                KeyCode::Enter,
+            ],
+            &mut ide_state,
+        );
+
+        let saved = std::fs::read_to_string(&notes_path);
+        let loaded = Notes::load(&notes_path);
+        assert_eq!(saved?, "1\t0\tout\n");
+        assert_eq!(loaded?, ide_state.notes);
+        assert_eq!(
+            ide_state.message,
+            Some(format!("saved notes to {}", notes_path.display()))
+        );
+
+        // Esc leaves the note as it was
+        type_keys(
+            &[KeyCode::Char('a'), KeyCode::Char('!'), KeyCode::Esc],
+            &mut ide_state,
+        );
+        assert_eq!(ide_state.notes.get(&Position { x: 1, y: 0 }), Some("out"));
+
+        // clearing the only note removes the sidecar file
+        type_keys(
+            &[
+                KeyCode::Char('a'),
+                KeyCode::Backspace,
+                KeyCode::Backspace,
+                KeyCode::Backspace,
+                KeyCode::Enter,
+            ],
+            &mut ide_state,
+        );
+        assert!(!notes_path.exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn running_pauses_at_breakpoints() -> GenericResult {
+        let mut program = Program::from_str("1.2.3.@")?;
+        let mut execution =
+            ExecutionState::new(program.clone(), false, InputBuffer::new(), Vec::new());
+        let mut ide_state = IDEState::new();
+        ide_state.timeline.reset(&execution);
+
+        ide_state.view_center = Position { x: 4, y: 0 };
+        handle_key(
+            Event::Key(KeyEvent::from(KeyCode::Char('x'))),
+            &mut ide_state,
+            &mut execution,
+            &mut program,
+        );
+        assert!(ide_state.breakpoints.contains(&Position { x: 4, y: 0 }));
+
+        ide_state.view_center = Position { x: 0, y: 1 };
+        let mut terminal = Terminal::new(TestBackend::new(40, 20))?;
+        terminal.draw(|f| ui(f, &execution, &program, &ide_state))?;
+        assert!(terminal
+            .backend()
+            .buffer()
+            .content
+            .iter()
+            .any(|cell| cell.symbol == "3" && cell.bg == Color::Blue));
+
+        ide_state.paused = false;
+        for _ in 0..10 {
+            handle_tick(&mut ide_state, &mut execution);
+        }
+        assert!(ide_state.paused);
+        assert_eq!(execution.pointer.position, Position { x: 4, y: 0 });
+        assert_eq!(execution.output, b"1 2 ");
+        assert_eq!(ide_state.message.as_deref(), Some("breakpoint at (4, 0)"));
+
+        // resuming runs past the breakpoint
+        ide_state.paused = false;
+        for _ in 0..10 {
+            handle_tick(&mut ide_state, &mut execution);
+        }
+        assert!(execution.terminated);
+        assert_eq!(execution.output, b"1 2 3 ");
+
+        Ok(())
+    }
+
+    #[test]
+    fn seeking_forward_stops_at_breakpoints() -> GenericResult {
+        let mut program = Program::from_str("1.2.3.@")?;
+        let mut execution =
+            ExecutionState::new(program.clone(), false, InputBuffer::new(), Vec::new());
+        let mut ide_state = IDEState::new();
+        ide_state.timeline.reset(&execution);
+        ide_state.breakpoints.insert(Position { x: 2, y: 0 });
+
+        let seek = Event::Key(KeyEvent::from(KeyCode::Char(']')));
+        handle_key(seek.clone(), &mut ide_state, &mut execution, &mut program);
+        assert_eq!(execution.instruction_count, 2);
+        assert_eq!(ide_state.message.as_deref(), Some("breakpoint at (2, 0)"));
+
+        handle_key(seek, &mut ide_state, &mut execution, &mut program);
+        assert!(execution.terminated);
+
+        // seeking backward replays past breakpoints
+        handle_key(
+            Event::Key(KeyEvent::from(KeyCode::Char('['))),
+            &mut ide_state,
+            &mut execution,
+            &mut program,
+        );
+        assert_eq!(execution.instruction_count, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn errors_keep_the_history() -> GenericResult {
+        let mut program = Program::from_str("10/@")?;
+        let mut execution =
+            ExecutionState::new(program.clone(), false, InputBuffer::new(), Vec::new());
+        let mut ide_state = IDEState::new();
+        ide_state.timeline.reset(&execution);
+
+        let mut press = |c: char, ide_state: &mut IDEState, execution: &mut IDEExecution| {
+            handle_key(
+                Event::Key(KeyEvent::from(KeyCode::Char(c))),
+                ide_state,
+                execution,
+                &mut program,
+            );
+        };
+
+        for _ in 0..3 {
+            press('t', &mut ide_state, &mut execution);
+        }
+        assert!(matches!(
+            ide_state.error,
+            Some(ExecutionError::DivisionByZero { .. })
+        ));
+        // the failed division is undone, leaving the pointer on it
+        assert_eq!(execution.pointer.position, Position { x: 2, y: 0 });
+        assert_eq!(execution.stack.items(), vec![1, 0]);
+        assert_eq!(ide_state.history.len(), 2);
+
+        press('b', &mut ide_state, &mut execution);
+        assert!(ide_state.error.is_none());
+        assert_eq!(execution.pointer.position, Position { x: 1, y: 0 });
+        assert_eq!(execution.stack.items(), vec![1]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn seeking_stops_when_waiting_for_input() -> GenericResult {
+        let mut program = Program::from_str(INPUT)?;
+        let mut execution =
+            ExecutionState::new(program.clone(), false, InputBuffer::new(), Vec::new());
+        let mut ide_state = IDEState::new();
+        ide_state.timeline.reset(&execution);
+
+        let seek = Event::Key(KeyEvent::from(KeyCode::Char(']')));
+        handle_key(seek.clone(), &mut ide_state, &mut execution, &mut program);
+        assert_eq!(execution.instruction_count, 0);
+        assert!(!execution.terminated);
+        assert_eq!(
+            ide_state.message.as_deref(),
+            Some("waiting for input (press :)")
+        );
+
+        execution.input.bytes.extend(b"42\n");
+        handle_key(seek, &mut ide_state, &mut execution, &mut program);
+        assert!(execution.terminated);
+        assert_eq!(String::from_utf8(execution.output.clone())?, "42 10 ");
+
+        Ok(())
+    }
 }
@@ -0,0 +1,157 @@
+use std::{collections::VecDeque, str::FromStr};
+
+use crate::{
+    execution::{ExecutionError, ExecutionState},
+    program::Program,
+};
+
+/// What an `Interpreter` is doing after a step.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "wasm", wasm_bindgen::prelude::wasm_bindgen)]
+pub enum Status {
+    /// The program can keep running.
+    Running,
+    /// The next instruction reads input, but none has been fed to the interpreter yet.
+    WaitingForInput,
+    /// The program has terminated.
+    Terminated,
+}
+
+/// Runs a program one step at a time, without blocking on input or writing to stdout,
+/// for hosts like a browser event loop that drive execution themselves.
+///
+/// Output is collected in memory, and input is fed in as it becomes available.
+/// Stepping onto `&` or `~` with no input waiting leaves the pointer where it is,
+/// rather than reading the end of the input.
+/// `&` reads a number up to the first byte that isn't a digit,
+/// so feed whole numbers (followed by a newline, say) at once.
+pub struct Interpreter {
+    execution: ExecutionState<VecDeque<u8>, Vec<u8>>,
+}
+
+impl Interpreter {
+    pub fn new(program: Program) -> Self {
+        Interpreter {
+            execution: ExecutionState::new(program, false, VecDeque::new(), Vec::new()),
+        }
+    }
+
+    /// Creates an interpreter for the program in `source`.
+    pub fn from_source(source: &str) -> Self {
+        // parsing a program from a string can't fail
+        Interpreter::new(Program::from_str(source).unwrap())
+    }
+
+    /// Queues up bytes for the program to read.
+    pub fn feed_input(&mut self, bytes: &[u8]) {
+        self.execution.input.extend(bytes);
+    }
+
+    /// Executes one step, unless the program has terminated or is waiting for input.
+    pub fn step(&mut self) -> Result<Status, ExecutionError> {
+        if self.status() == Status::Running {
+            self.execution.step()?;
+        }
+
+        Ok(self.status())
+    }
+
+    /// Executes up to `n` steps, stopping early if the program terminates or waits for input.
+    pub fn step_many(&mut self, n: u64) -> Result<Status, ExecutionError> {
+        for _ in 0..n {
+            if self.step()? != Status::Running {
+                break;
+            }
+        }
+
+        Ok(self.status())
+    }
+
+    pub fn status(&self) -> Status {
+        if self.execution.terminated {
+            Status::Terminated
+        } else if self.execution.wants_input() && self.execution.input.is_empty() {
+            Status::WaitingForInput
+        } else {
+            Status::Running
+        }
+    }
+
+    /// Everything the program has written so far.
+    pub fn output(&self) -> &[u8] {
+        &self.execution.output
+    }
+
+    /// Removes and returns everything the program has written since the last call.
+    pub fn take_output(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.execution.output)
+    }
+
+    /// The underlying execution, for inspecting the pointer, stack, and program.
+    pub fn execution(&self) -> &ExecutionState<VecDeque<u8>, Vec<u8>> {
+        &self.execution
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        examples::{HELLO_WORLD, INPUT},
+        interpreter::{Interpreter, Status},
+    };
+
+    type GenericResult = Result<(), Box<dyn std::error::Error>>;
+
+    #[test]
+    fn hello_world() -> GenericResult {
+        let mut interpreter = Interpreter::from_source(HELLO_WORLD);
+
+        let mut steps = 0;
+        while interpreter.step()? == Status::Running {
+            steps += 1;
+        }
+
+        assert_eq!(steps, 106);
+        assert_eq!(interpreter.status(), Status::Terminated);
+        assert_eq!(interpreter.output(), b"Hello, World!\n");
+
+        // stepping a terminated program does nothing
+        assert_eq!(interpreter.step()?, Status::Terminated);
+        assert_eq!(interpreter.execution().instruction_count, 107);
+
+        Ok(())
+    }
+
+    #[test]
+    fn waits_for_input() -> GenericResult {
+        let mut interpreter = Interpreter::from_source(INPUT);
+
+        assert_eq!(interpreter.step_many(100)?, Status::WaitingForInput);
+        assert_eq!(interpreter.execution().instruction_count, 0);
+
+        interpreter.feed_input(b"42\n");
+        assert_eq!(interpreter.step()?, Status::Running);
+        assert_eq!(interpreter.step()?, Status::Running);
+        assert_eq!(interpreter.take_output(), b"42 ");
+
+        assert_eq!(interpreter.step_many(100)?, Status::Terminated);
+        assert_eq!(interpreter.output(), b"10 ");
+
+        Ok(())
+    }
+
+    #[test]
+    fn waits_for_more_input() -> GenericResult {
+        let mut interpreter = Interpreter::from_source("~,~,@");
+
+        interpreter.feed_input(b"a");
+        assert_eq!(interpreter.step_many(100)?, Status::WaitingForInput);
+        assert_eq!(interpreter.output(), b"a");
+
+        interpreter.feed_input(b"b");
+        assert_eq!(interpreter.step_many(100)?, Status::Terminated);
+        assert_eq!(interpreter.output(), b"ab");
+
+        Ok(())
+    }
+}
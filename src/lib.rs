@@ -1,7 +1,202 @@
 #[macro_use]
 extern crate lazy_static;
 
+pub mod dialect;
+pub mod events;
 pub mod examples;
 pub mod execution;
 pub mod ide;
+pub mod interpreter;
 pub mod program;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+use std::{
+    fmt,
+    fmt::{Display, Formatter},
+    io,
+    io::{Read, Write},
+    time::{Duration, Instant},
+};
+
+use humantime::format_duration;
+use itertools::Itertools;
+use separator::Separatable;
+
+use crate::{
+    dialect::Dialect,
+    execution::{ExecutionError, ExecutionState, RunOutcome},
+    program::Program,
+};
+
+/// How long a program took to run.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TimingReport {
+    pub instruction_count: u64,
+    pub duration: Duration,
+    pub instructions_per_second: f64,
+}
+
+impl TimingReport {
+    pub fn new(instruction_count: u64, duration: Duration) -> Self {
+        TimingReport {
+            instruction_count,
+            duration,
+            instructions_per_second: instruction_count as f64 / duration.as_secs_f64(),
+        }
+    }
+}
+
+/// Runs the program to termination, timing its execution.
+pub fn time<R: Read, O: Write>(
+    execution: &mut ExecutionState<R, O>,
+) -> Result<TimingReport, ExecutionError> {
+    time_with_limit(execution, u64::MAX).map(|(report, _)| report)
+}
+
+/// Runs the program for at most `max_steps` instructions, timing its execution.
+pub fn time_with_limit<R: Read, O: Write>(
+    execution: &mut ExecutionState<R, O>,
+    max_steps: u64,
+) -> Result<(TimingReport, RunOutcome), ExecutionError> {
+    let start = Instant::now();
+    let outcome = execution.run_with_limit(max_steps)?;
+
+    Ok((
+        TimingReport::new(execution.instruction_count, start.elapsed()),
+        outcome,
+    ))
+}
+
+impl Display for TimingReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Executed {} instructions in {} ({} instructions/second)",
+            self.instruction_count,
+            format_duration(self.duration),
+            (self.instructions_per_second as u64).separated_string()
+        )
+    }
+}
+
+/// Prints a timing report to stderr.
+pub fn print_timing(report: &TimingReport) {
+    eprintln!("{}", report);
+}
+
+/// Whether the program prints its own source, running it with no input
+/// for at most `max_steps` instructions.
+/// A program that fails or doesn't terminate in time is not a quine.
+///
+/// The output is compared to the program as rendered by its `Display` implementation,
+/// with trailing spaces on each line and trailing newlines (`\n` or `\r\n`) removed from both,
+/// since they make no difference to the program.
+pub fn is_quine(program: &Program, dialect: Dialect, max_steps: u64) -> bool {
+    let mut execution = ExecutionState::builder(program.clone())
+        .dialect(dialect)
+        .build(io::empty(), Vec::new());
+    if !matches!(
+        execution.run_with_limit(max_steps),
+        Ok(RunOutcome::Terminated)
+    ) {
+        return false;
+    }
+
+    let (_, _, _, output) = execution.into_parts();
+    match String::from_utf8(output) {
+        Ok(output) => normalize_quine_text(&output) == normalize_quine_text(&program.to_string()),
+        Err(_) => false,
+    }
+}
+
+fn normalize_quine_text(text: &str) -> String {
+    text.lines()
+        .map(|line| line.trim_end_matches(' '))
+        .join("\n")
+        .trim_end_matches('\n')
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use crate::{
+        dialect::Dialect,
+        examples::{ERATOSTHENES, HELLO_WORLD, QUINE},
+        execution::{ExecutionState, RunOutcome},
+        is_quine, normalize_quine_text,
+        program::Program,
+        time, time_with_limit,
+    };
+
+    type GenericResult = Result<(), Box<dyn std::error::Error>>;
+
+    #[test]
+    fn time_reports_instruction_count() -> GenericResult {
+        let program = Program::from_str(HELLO_WORLD)?;
+        let input = [];
+        let mut execution = ExecutionState::new(program, false, input.as_slice(), Vec::new());
+
+        let report = time(&mut execution)?;
+
+        assert_eq!(report.instruction_count, 107);
+        assert_eq!(report.instruction_count, execution.instruction_count);
+        assert!(report.instructions_per_second > 0.0);
+        assert_eq!(
+            "Hello, World!\n",
+            String::from_utf8(execution.output).unwrap()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn time_with_limit_stops_runaway_programs() -> GenericResult {
+        let program = Program::from_str(ERATOSTHENES)?;
+        let input = [];
+        let mut execution = ExecutionState::new(program, false, input.as_slice(), Vec::new());
+
+        let (report, outcome) = time_with_limit(&mut execution, 1000)?;
+
+        assert_eq!(outcome, RunOutcome::LimitReached);
+        assert_eq!(report.instruction_count, 1000);
+
+        Ok(())
+    }
+
+    #[test]
+    fn quine_is_a_quine() -> GenericResult {
+        let quine = Program::from_str(QUINE)?;
+        assert!(is_quine(&quine, Dialect::Befunge93, 100_000));
+
+        // ...but not if it runs out of steps first
+        assert!(!is_quine(&quine, Dialect::Befunge93, 100));
+
+        assert!(!is_quine(
+            &Program::from_str(HELLO_WORLD)?,
+            Dialect::Befunge93,
+            100_000
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn quine_check_ignores_trailing_whitespace() -> GenericResult {
+        assert_eq!(normalize_quine_text("ab  \r\n cd\n\n\n"), "ab\n cd");
+        assert_eq!(normalize_quine_text("ab\n\ncd \n"), "ab\n\ncd");
+        assert_eq!(normalize_quine_text(""), "");
+
+        // the empty program never terminates, and one that only exits prints nothing
+        assert!(!is_quine(&Program::from_str("")?, Dialect::Befunge93, 1000));
+        assert!(!is_quine(
+            &Program::from_str("@")?,
+            Dialect::Befunge93,
+            1000
+        ));
+
+        Ok(())
+    }
+}
@@ -1,8 +1,26 @@
-use std::{error::Error, ffi::OsString, fmt, fmt::Display, io, str::FromStr, time::Instant};
+use std::{
+    error::Error,
+    ffi::OsString,
+    fmt,
+    fmt::Display,
+    fs, io,
+    io::{Read, Write},
+    mem::size_of,
+    path::Path,
+    str::FromStr,
+    thread,
+    time::{Duration, Instant},
+};
 
 use clap::{Args, Parser, Subcommand};
-use fungoid::{examples::EXAMPLES, execution::ExecutionState, program::Program};
-use humantime::format_duration;
+use fungoid::{
+    dialect::{convert, Dialect},
+    examples::EXAMPLES,
+    execution::{ExecutionError, ExecutionState, RunOutcome, TraceFormat},
+    is_quine,
+    program::{extract_fenced_program, Program},
+    TimingReport,
+};
 use itertools::Itertools;
 use separator::Separatable;
 
@@ -27,24 +45,81 @@ enum Commands {
     /// Run a program
     #[command(arg_required_else_help = true)]
     Run {
-        /// The path to the file to read the program from
+        /// The path to the file to read the program from, or `-` to read it from stdin
         file: OsString,
-        /// Enable execution tracing
-        #[arg(long)]
-        trace: bool,
-        /// Enable profiling
+        /// Run the first ```befunge fenced code block in a Markdown file
         #[arg(long)]
-        profile: bool,
+        extract_fence: bool,
+        #[command(flatten)]
+        options: RunArgs,
     },
     /// Start the TUI IDE
     #[command(arg_required_else_help = true)]
     Ide {
         /// The path to the file to open
         file: OsString,
+        /// Pause once the program has run this many instructions, showing progress towards it
+        #[arg(long)]
+        max_steps: Option<u64>,
+    },
+    /// Start the TUI IDE paused on the first instruction, following the pointer
+    #[command(arg_required_else_help = true)]
+    Debug {
+        /// The path to the file to open
+        file: OsString,
+        /// Pause once the program has run this many instructions, showing progress towards it
+        #[arg(long)]
+        max_steps: Option<u64>,
+    },
+    /// Rewrite a program's layout for another dialect, printing it to stdout.
+    ///
+    /// Converting to Befunge-93 pads the program to the 80x25 playfield;
+    /// converting to Funge-98 trims trailing whitespace.
+    /// The program's instructions are never changed, but warnings are printed
+    /// for cells that appear to rely on Befunge-93's playfield wrapping around.
+    #[command(arg_required_else_help = true)]
+    Convert {
+        /// The path to the file to read the program from
+        file: OsString,
+        /// The dialect the program was written for
+        #[arg(long, value_parser = Dialect::from_str)]
+        from: Dialect,
+        /// The dialect to convert the program to
+        #[arg(long, value_parser = Dialect::from_str)]
+        to: Dialect,
+    },
+    /// Rewrite a program's whitespace into its canonical layout.
+    ///
+    /// Trailing spaces on each line and trailing blank lines are removed.
+    #[command(arg_required_else_help = true)]
+    Fmt {
+        /// The path to the file to format
+        file: OsString,
+        /// Print the formatted program to stdout instead of rewriting the file
+        #[arg(long, conflicts_with = "check")]
+        stdout: bool,
+        /// Don't write anything, but exit with an error if the file is not already formatted
+        #[arg(long)]
+        check: bool,
+    },
+    /// Check whether a program prints its own source, ignoring trailing whitespace
+    #[command(arg_required_else_help = true)]
+    CheckQuine {
+        /// The path to the file to read the program from
+        file: OsString,
+        /// Give up after this many instructions
+        #[arg(long, default_value_t = 1_000_000)]
+        max_steps: u64,
+        /// The dialect to run the program in
+        /// [default: guessed from the file extension (.b98 for funge98), otherwise befunge93]
+        #[arg(long, value_parser = Dialect::from_str)]
+        dialect: Option<Dialect>,
     },
     /// Interact with the bundled example programs.
     #[command(arg_required_else_help = true)]
     Examples(ExamplesArgs),
+    /// Print the version, supported dialects, and optional features compiled in, for bug reports
+    Info,
 }
 
 #[derive(Debug, Args)]
@@ -55,9 +130,11 @@ struct ExamplesArgs {
 
 #[derive(Debug, Subcommand)]
 enum ExamplesCommands {
-    /// Print the available bundled example programs
-    #[command(arg_required_else_help = true)]
+    /// Print the names and descriptions of the bundled example programs
     List,
+    /// Print the examples whose name or description contains a term, ignoring case
+    #[command(arg_required_else_help = true)]
+    Search { term: String },
     /// Print one of the example programs to stdout
     #[command(arg_required_else_help = true)]
     Print { example: String },
@@ -65,34 +142,199 @@ enum ExamplesCommands {
     #[command(arg_required_else_help = true)]
     Run {
         /// The name of the example to run
-        example: String,
-        /// Enable execution tracing
-        #[arg(long)]
-        trace: bool,
-        /// Enable profiling
+        #[arg(required_unless_present = "all")]
+        example: Option<String>,
+        /// Run every example with empty input, reporting how each one ended
+        #[arg(long, conflicts_with = "example")]
+        all: bool,
+        /// With --all, stop each example after this many instructions [default: 100000]
         #[arg(long)]
-        profile: bool,
+        max_steps: Option<u64>,
+        #[command(flatten)]
+        options: RunArgs,
     },
 }
 
+#[derive(Debug, Args)]
+struct RunArgs {
+    /// Enable execution tracing
+    #[arg(long)]
+    trace: bool,
+    /// Write the execution trace to this file instead of stderr (implies --trace)
+    #[arg(long)]
+    trace_file: Option<OsString>,
+    /// Write the execution trace as one JSON object per line
+    #[cfg(feature = "serde")]
+    #[arg(long)]
+    trace_json: bool,
+    /// Enable profiling
+    #[arg(long)]
+    profile: bool,
+    /// With --profile, print only the number of instructions executed
+    #[arg(long, requires = "profile")]
+    quiet: bool,
+    /// Write this after each number that `.` outputs
+    #[arg(long, default_value = " ")]
+    numeric_separator: String,
+    /// Fail when the program pops from an empty stack, instead of popping 0
+    #[arg(long)]
+    strict_stack: bool,
+    /// Fail if the program gets stuck repeating itself without any input or output
+    #[arg(long)]
+    detect_loops: bool,
+    /// Report the memory used by the playfield and stack after execution
+    #[arg(long)]
+    measure_memory: bool,
+    /// Pause for this long after each instruction (e.g. "10ms")
+    #[arg(long, value_parser = humantime::parse_duration, conflicts_with = "ips")]
+    step_delay: Option<Duration>,
+    /// Execute at most this many instructions per second
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    ips: Option<u64>,
+    /// Read the program's input from this file instead of stdin
+    #[arg(short, long)]
+    input: Option<OsString>,
+    /// Write the program's output to this file instead of stdout
+    #[arg(short, long)]
+    output: Option<OsString>,
+    /// Only write the output of every Nth `.` or `,` instruction, to sample very long output
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    every: Option<u64>,
+    /// The dialect whose instruction set to accept
+    /// [default: guessed from the file extension (.b98 for funge98), otherwise befunge93]
+    #[arg(long, value_parser = Dialect::from_str)]
+    dialect: Option<Dialect>,
+}
+
+impl RunArgs {
+    /// The `--dialect` if given, otherwise the dialect suggested by the program's file extension.
+    fn dialect(&self, file: Option<&OsString>) -> Dialect {
+        self.dialect
+            .or_else(|| file.and_then(|file| Dialect::from_extension(Path::new(file))))
+            .unwrap_or_default()
+    }
+
+    fn input(&self) -> GenericResult<Box<dyn Read>> {
+        Ok(match &self.input {
+            Some(path) => Box::new(io::BufReader::new(fs::File::open(path)?)),
+            None => Box::new(io::stdin()),
+        })
+    }
+
+    fn trace_format(&self) -> TraceFormat {
+        #[cfg(feature = "serde")]
+        if self.trace_json {
+            return TraceFormat::Json;
+        }
+
+        TraceFormat::Text
+    }
+
+    fn output(&self) -> GenericResult<Box<dyn Write>> {
+        let output: Box<dyn Write> = match &self.output {
+            Some(path) => Box::new(io::BufWriter::new(fs::File::create(path)?)),
+            None => Box::new(io::stdout()),
+        };
+
+        Ok(match self.every {
+            Some(every) => Box::new(Sampled::new(output, every)),
+            None => output,
+        })
+    }
+
+    fn step_delay(&self) -> Option<Duration> {
+        self.step_delay.or_else(|| {
+            self.ips
+                .map(|ips| Duration::from_secs_f64(1.0 / ips as f64))
+        })
+    }
+}
+
 fn cli() -> GenericResult<()> {
     match Cli::parse().command {
         Commands::Run {
             file,
-            trace,
-            profile,
+            extract_fence,
+            options,
         } => {
+            let program = load_program(&file, extract_fence, &options, io::stdin())?;
+
+            if let Some(code) = run_program(program, options.dialect(Some(&file)), &options)? {
+                std::process::exit(code);
+            }
+
+            Ok(())
+        }
+
+        Commands::Ide { file, max_steps } => {
+            let program = Program::from_file(&file)?;
+
+            fungoid::ide::ide(program, Path::new(&file), max_steps)?;
+
+            Ok(())
+        }
+
+        Commands::Debug { file, max_steps } => {
+            let program = Program::from_file(&file)?;
+
+            fungoid::ide::debug(program, Path::new(&file), max_steps)?;
+
+            Ok(())
+        }
+
+        Commands::Convert { file, from, to } => {
             let program = Program::from_file(&file)?;
 
-            run_program(program, trace, profile)?;
+            let conversion = convert(&program, from, to);
+            for warning in conversion.warnings {
+                eprintln!("Warning: {}", warning);
+            }
+            println!("{}", conversion.source);
+
+            Ok(())
+        }
+
+        Commands::Fmt {
+            file,
+            stdout,
+            check,
+        } => {
+            let source = fs::read_to_string(&file)?;
+            let formatted = format_source(&source)?;
+
+            if check {
+                if formatted != source {
+                    return Err(format!("{:?} is not formatted", file).into());
+                }
+            } else if stdout {
+                print!("{}", formatted);
+            } else if formatted != source {
+                fs::write(&file, formatted)?;
+            }
 
             Ok(())
         }
 
-        Commands::Ide { file } => {
+        Commands::CheckQuine {
+            file,
+            max_steps,
+            dialect,
+        } => {
             let program = Program::from_file(&file)?;
+            let dialect = dialect
+                .or_else(|| Dialect::from_extension(Path::new(&file)))
+                .unwrap_or_default();
+
+            if is_quine(&program, dialect, max_steps) {
+                println!("{:?} is a quine", file);
+                Ok(())
+            } else {
+                Err(format!("{:?} is not a quine", file).into())
+            }
+        }
 
-            fungoid::ide::ide(program)?;
+        Commands::Info => {
+            print!("{}", info());
 
             Ok(())
         }
@@ -100,7 +342,15 @@ fn cli() -> GenericResult<()> {
         Commands::Examples(ExamplesArgs {
             command: ExamplesCommands::List,
         }) => {
-            println!("{}", EXAMPLES.keys().sorted().join("\n"));
+            print!("{}", example_list(None));
+
+            Ok(())
+        }
+
+        Commands::Examples(ExamplesArgs {
+            command: ExamplesCommands::Search { term },
+        }) => {
+            print!("{}", example_list(Some(&term)));
 
             Ok(())
         }
@@ -117,14 +367,53 @@ fn cli() -> GenericResult<()> {
         Commands::Examples(ExamplesArgs {
             command:
                 ExamplesCommands::Run {
-                    example,
-                    trace,
-                    profile,
+                    all: true,
+                    max_steps,
+                    options,
+                    ..
                 },
         }) => {
+            let width = EXAMPLES.keys().map(|name| name.len()).max().unwrap_or(0);
+            let mut failures = 0;
+            let max_steps = max_steps.unwrap_or(100_000);
+            for (name, instructions, result) in smoke_test_examples(max_steps, &options) {
+                let outcome = match result {
+                    Ok(RunOutcome::Terminated) => "ended".to_string(),
+                    Ok(RunOutcome::LimitReached | RunOutcome::TimedOut) => {
+                        "still running".to_string()
+                    }
+                    Err(e) => {
+                        failures += 1;
+                        format!("failed ({})", e)
+                    }
+                };
+                println!(
+                    "{:width$} — {} after {} instructions",
+                    name,
+                    outcome,
+                    instructions.separated_string()
+                );
+            }
+
+            if failures > 0 {
+                return Err(format!("{} examples failed", failures).into());
+            }
+
+            Ok(())
+        }
+
+        Commands::Examples(ExamplesArgs {
+            command: ExamplesCommands::Run {
+                example, options, ..
+            },
+        }) => {
+            // clap requires an example unless --all is given
+            let example = example.unwrap();
             let program = Program::from_str(get_example(example.as_str())?).unwrap();
 
-            run_program(program, trace, profile)?;
+            if let Some(code) = run_program(program, options.dialect(None), &options)? {
+                std::process::exit(code);
+            }
 
             Ok(())
         }
@@ -154,9 +443,64 @@ impl Error for NoExampleFound {
     }
 }
 
+/// The version, dialects, and enabled Cargo features, one per line.
+fn info() -> String {
+    let dialects = [Dialect::Befunge93, Dialect::Funge98]
+        .iter()
+        .map(|dialect| {
+            if *dialect == Dialect::default() {
+                format!("{} (default)", dialect)
+            } else {
+                dialect.to_string()
+            }
+        })
+        .join(", ");
+
+    let features = [
+        ("clipboard", cfg!(feature = "clipboard")),
+        ("interrupt", cfg!(feature = "interrupt")),
+        ("serde", cfg!(feature = "serde")),
+        ("wasm", cfg!(feature = "wasm")),
+    ]
+    .iter()
+    .filter(|(_, enabled)| *enabled)
+    .map(|(feature, _)| *feature)
+    .join(", ");
+
+    format!(
+        "fungoid {}\nDialects: {}\nFeatures: {}\n",
+        env!("CARGO_PKG_VERSION"),
+        dialects,
+        if features.is_empty() {
+            "none"
+        } else {
+            &features
+        }
+    )
+}
+
+/// Each example's name and description, one per line,
+/// limited to those containing `term` (ignoring case) if given.
+fn example_list(term: Option<&str>) -> String {
+    let width = EXAMPLES.keys().map(|name| name.len()).max().unwrap_or(0);
+    let term = term.map(str::to_lowercase);
+
+    EXAMPLES
+        .iter()
+        .filter(|(name, example)| {
+            term.as_ref().is_none_or(|term| {
+                name.to_lowercase().contains(term)
+                    || example.description.to_lowercase().contains(term)
+            })
+        })
+        .sorted_by_key(|(name, _)| **name)
+        .map(|(name, example)| format!("{:width$} — {}\n", name, example.description))
+        .collect()
+}
+
 fn get_example(example: &str) -> GenericResult<&str> {
-    if let Some(program) = EXAMPLES.get(example) {
-        Ok(program)
+    if let Some(example) = EXAMPLES.get(example) {
+        Ok(example.source)
     } else {
         Err(Box::new(NoExampleFound::new(format!(
             "No example named '{}'.\nExamples:\n{}",
@@ -166,36 +510,705 @@ fn get_example(example: &str) -> GenericResult<&str> {
     }
 }
 
-fn run_program(program: Program, trace: bool, profile: bool) -> GenericResult<()> {
-    let input = &mut io::stdin();
-    let output = &mut io::stdout();
-    let mut program_state = ExecutionState::new(program, trace, input, output);
+/// Runs every example, in name order, with empty input and for at most `max_steps` instructions,
+/// returning how many instructions each one executed and how it stopped.
+fn smoke_test_examples(
+    max_steps: u64,
+    options: &RunArgs,
+) -> Vec<(&'static str, u64, Result<RunOutcome, ExecutionError>)> {
+    EXAMPLES
+        .iter()
+        .sorted_by_key(|(name, _)| **name)
+        .map(|(name, example)| {
+            let mut execution = ExecutionState::builder(Program::from_str(example.source).unwrap())
+                .strict_stack(options.strict_stack)
+                .detect_loops(options.detect_loops)
+                .dialect(options.dialect(None))
+                .build(io::empty(), io::sink());
+            let result = execution.run_with_limit(max_steps);
+
+            (*name, execution.instruction_count, result)
+        })
+        .collect()
+}
+
+/// A writer that passes only every `every`th write on to `inner`, dropping the rest.
+/// Each `.` and `,` instruction writes its output in one go,
+/// so numbers and multi-byte characters are kept or dropped whole.
+struct Sampled<W: Write> {
+    inner: W,
+    every: u64,
+    count: u64,
+}
+
+impl<W: Write> Sampled<W> {
+    fn new(inner: W, every: u64) -> Self {
+        assert!(every > 0, "Can't sample every 0th write");
+        Sampled {
+            inner,
+            every,
+            count: 0,
+        }
+    }
+}
+
+impl<W: Write> Write for Sampled<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.count += 1;
+        if self.count.is_multiple_of(self.every) {
+            self.inner.write_all(buf)?;
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Loads the program to run from `file`, or from `stdin` if `file` is `-`.
+fn load_program<R: Read>(
+    file: &OsString,
+    extract_fence: bool,
+    options: &RunArgs,
+    stdin: R,
+) -> GenericResult<Program> {
+    let from_stdin = file == "-";
+    if from_stdin && options.input.is_none() {
+        return Err("--input is required when reading the program from stdin".into());
+    }
+
+    if extract_fence {
+        let text = if from_stdin {
+            io::read_to_string(stdin)?
+        } else {
+            fs::read_to_string(file)?
+        };
+        let source = extract_fenced_program(&text)
+            .ok_or_else(|| format!("No befunge code block found in {:?}", file))?;
+        Ok(Program::from_str(&source)?)
+    } else if from_stdin {
+        Ok(Program::from_reader(stdin)?)
+    } else {
+        Ok(Program::from_file(file)?)
+    }
+}
+
+/// Returns the canonical layout of a program's source.
+fn format_source(source: &str) -> GenericResult<String> {
+    let program = Program::from_str(source)?;
+    let formatted = program.to_string();
+
+    if formatted.is_empty() {
+        Ok(formatted)
+    } else {
+        Ok(format!("{}\n", formatted))
+    }
+}
+
+/// Writes the `--profile` report, or just the instruction count if `quiet`.
+fn write_profile<R: Read, O: Write>(
+    w: &mut impl Write,
+    program_state: &ExecutionState<R, O>,
+    duration: Duration,
+    quiet: bool,
+) -> io::Result<()> {
+    if quiet {
+        return writeln!(w, "{}", program_state.instruction_count);
+    }
+
+    writeln!(
+        w,
+        "{}",
+        TimingReport::new(program_state.instruction_count, duration)
+    )?;
+
+    let underflows = program_state.underflow_count();
+    if underflows > 0 {
+        writeln!(
+            w,
+            "Popped from an empty stack {} times",
+            underflows.separated_string()
+        )?;
+    }
+
+    writeln!(w, "Instructions executed:")?;
+    for (instruction, count) in program_state
+        .instruction_histogram()
+        .iter()
+        .sorted_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then(a.cmp(b)))
+    {
+        writeln!(w, "  '{}' {:>12}", instruction, count.separated_string())?;
+    }
+
+    Ok(())
+}
+
+/// Writes the `--measure-memory` report: how many cells of the playfield hold something,
+/// the peak stack depth, and roughly how many bytes storing them took.
+/// The byte estimate counts every cell in storage, including the spaces of a dense grid.
+fn write_memory_report<R: Read, O: Write>(
+    w: &mut impl Write,
+    program_state: &ExecutionState<R, O>,
+) -> io::Result<()> {
+    let cells = program_state.program.cell_count();
+    let peak_depth = program_state.stack.peak_depth();
+    let bytes = program_state.program.estimated_bytes() + peak_depth * size_of::<isize>();
+    writeln!(
+        w,
+        "Playfield holds {} cells, peak stack depth was {} (storage about {} bytes)",
+        cells.separated_string(),
+        peak_depth.separated_string(),
+        bytes.separated_string()
+    )
+}
+
+/// Runs the program with the given options,
+/// returning the exit code it asked for with `q`, if any.
+fn run_program(
+    program: Program,
+    dialect: Dialect,
+    options: &RunArgs,
+) -> GenericResult<Option<i32>> {
+    let mut input = options.input()?;
+    let mut output = options.output()?;
+    // a dense grid is faster, but to_dense keeps far-apart programs sparse
+    let mut program_state = ExecutionState::builder(program.to_dense())
+        .trace(options.trace || options.trace_file.is_some())
+        .trace_format(options.trace_format())
+        .track_underflow(options.profile)
+        .strict_stack(options.strict_stack)
+        .detect_loops(options.detect_loops)
+        .numeric_output_separator(&options.numeric_separator)
+        .profile(options.profile)
+        .dialect(dialect)
+        .build(input.as_mut(), output.as_mut());
+    if let Some(path) = &options.trace_file {
+        program_state.set_trace_output(Box::new(io::BufWriter::new(fs::File::create(path)?)));
+    }
 
     let start = Instant::now();
-    program_state.run()?;
+    run_to_termination(&mut program_state, options.step_delay())?;
     let duration = start.elapsed();
 
-    if profile {
-        eprintln!(
-            "Executed {} instructions in {} ({} instructions/second)",
-            program_state.instruction_count,
-            format_duration(duration),
-            ((program_state.instruction_count as f64 / duration.as_secs_f64()) as u64)
-                .separated_string()
-        );
+    if options.profile {
+        write_profile(&mut io::stderr(), &program_state, duration, options.quiet)?;
+    }
+
+    if options.measure_memory {
+        write_memory_report(&mut io::stderr(), &program_state)?;
+    }
+
+    program_state.output.flush()?;
+    program_state.flush_trace()?;
+
+    Ok(program_state.exit_code)
+}
+
+/// Runs the program, pausing for `delay` after each instruction if given.
+fn run_to_termination<R: Read, O: Write>(
+    program_state: &mut ExecutionState<R, O>,
+    delay: Option<Duration>,
+) -> GenericResult<()> {
+    #[cfg(feature = "interrupt")]
+    let mut interrupts = Interrupts::install()?;
+
+    while !program_state.terminated {
+        program_state.step()?;
+
+        if let Some(delay) = delay {
+            thread::sleep(delay);
+        }
+
+        #[cfg(feature = "interrupt")]
+        interrupts.report(program_state);
     }
 
     Ok(())
 }
 
+/// The number of times Ctrl-C has been pressed during the current run.
+#[cfg(feature = "interrupt")]
+static INTERRUPT_COUNT: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+/// Reports the execution state on the first Ctrl-C and exits on the second.
+#[cfg(feature = "interrupt")]
+struct Interrupts {
+    reported: bool,
+}
+
+#[cfg(feature = "interrupt")]
+impl Interrupts {
+    /// Starts counting interrupts for a new run.
+    /// The handler can only be set once per process, so later runs share it.
+    fn install() -> GenericResult<Self> {
+        use std::sync::{atomic::Ordering, Once};
+
+        static HANDLER: Once = Once::new();
+
+        INTERRUPT_COUNT.store(0, Ordering::SeqCst);
+        let mut installed = Ok(());
+        HANDLER.call_once(|| {
+            installed = ctrlc::set_handler(|| {
+                if INTERRUPT_COUNT.fetch_add(1, Ordering::SeqCst) > 0 {
+                    std::process::exit(130);
+                }
+            });
+        });
+        installed?;
+
+        Ok(Interrupts { reported: false })
+    }
+
+    fn report<R: Read, O: Write>(&mut self, program_state: &ExecutionState<R, O>) {
+        if !self.reported && INTERRUPT_COUNT.load(std::sync::atomic::Ordering::Relaxed) > 0 {
+            eprintln!("{}", interrupt_report(program_state));
+            self.reported = true;
+        }
+    }
+}
+
+#[cfg(feature = "interrupt")]
+fn interrupt_report<R: Read, O: Write>(program_state: &ExecutionState<R, O>) -> String {
+    format!(
+        "Interrupted after {} instructions at (x={}, y={}) on '{}' | stack: [{}]\nPress Ctrl-C again to exit.",
+        program_state.instruction_count,
+        program_state.pointer.position.x,
+        program_state.pointer.position.y,
+        program_state.program.get(&program_state.pointer.position),
+        program_state.stack.items().iter().join(" ")
+    )
+}
+
 #[cfg(test)]
 mod tests {
-    use clap::CommandFactory;
+    use std::{ffi::OsStr, fs, io::Write, path::PathBuf, str::FromStr, time::Duration};
+
+    use clap::{CommandFactory, Parser};
+    use fungoid::{
+        dialect::Dialect,
+        examples::{FACTORIAL, HELLO_WORLD},
+        execution::ExecutionState,
+        program::{Position, Program},
+    };
+
+    #[cfg(feature = "interrupt")]
+    use crate::interrupt_report;
+    use crate::{
+        example_list, format_source, info, load_program, run_program, smoke_test_examples,
+        write_memory_report, write_profile, Cli, Commands, ExamplesArgs, ExamplesCommands,
+        GenericResult, Sampled,
+    };
+
+    /// A path in the temporary directory, unique to this test process.
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("fungoid-{}-{}", std::process::id(), name))
+    }
+
+    /// Runs `program` as `fungoid run FILE ARGS...` would, sending its output to a temporary file,
+    /// and returns that output along with the exit code the program asked for, if any.
+    /// `file` is never read, but its extension picks the dialect.
+    fn run_cli(
+        file: &str,
+        args: &[&OsStr],
+        program: Program,
+    ) -> GenericResult<(String, Option<i32>)> {
+        let output_path = temp_path(&format!("{}.out", file));
+        let mut argv: Vec<&OsStr> = vec![
+            "fungoid".as_ref(),
+            "run".as_ref(),
+            file.as_ref(),
+            "--output".as_ref(),
+            output_path.as_os_str(),
+        ];
+        argv.extend_from_slice(args);
+        let cli = Cli::parse_from(argv);
+        let (file, options) = match cli.command {
+            Commands::Run { file, options, .. } => (file, options),
+            _ => panic!("expected the run command"),
+        };
+
+        let code = run_program(program, options.dialect(Some(&file)), &options);
+        let output = fs::read_to_string(&output_path);
+        fs::remove_file(&output_path)?;
 
-    use crate::Cli;
+        Ok((output?, code?))
+    }
 
     #[test]
     fn verify_command() {
         Cli::command().debug_assert()
     }
+
+    #[test]
+    fn ips_sets_step_delay() {
+        let cli = Cli::parse_from(["fungoid", "run", "prog.bf", "--ips", "4"]);
+        if let Commands::Run { options, .. } = cli.command {
+            assert_eq!(options.step_delay(), Some(Duration::from_millis(250)));
+        } else {
+            panic!("expected the run command");
+        }
+
+        let cli = Cli::parse_from(["fungoid", "run", "prog.bf", "--step-delay", "10ms"]);
+        if let Commands::Run { options, .. } = cli.command {
+            assert_eq!(options.step_delay(), Some(Duration::from_millis(10)));
+        } else {
+            panic!("expected the run command");
+        }
+    }
+
+    #[test]
+    fn ips_conflicts_with_step_delay() {
+        assert!(Cli::try_parse_from([
+            "fungoid",
+            "run",
+            "prog.bf",
+            "--ips",
+            "4",
+            "--step-delay",
+            "10ms"
+        ])
+        .is_err());
+    }
+
+    #[cfg(feature = "interrupt")]
+    #[test]
+    fn interrupt_report_describes_state() -> Result<(), Box<dyn std::error::Error>> {
+        let program = Program::from_str("12>")?;
+        let input = [];
+        let mut execution = ExecutionState::new(program, false, input.as_slice(), Vec::new());
+        execution.step()?;
+        execution.step()?;
+
+        assert_eq!(
+            interrupt_report(&execution),
+            "Interrupted after 2 instructions at (x=2, y=0) on '>' | stack: [1 2]\nPress Ctrl-C again to exit."
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn format_trims_trailing_whitespace() -> Result<(), Box<dyn std::error::Error>> {
+        assert_eq!(format_source("v  \n>1.@   \n\n  \n")?, "v\n>1.@\n");
+        assert_eq!(format_source("v\n>1.@\n")?, "v\n>1.@\n");
+        assert_eq!(format_source(" \n>1.@")?, "\n>1.@\n");
+        assert_eq!(format_source("   \n\n")?, "");
+
+        Ok(())
+    }
+
+    #[test]
+    fn input_reads_from_file() -> Result<(), Box<dyn std::error::Error>> {
+        let path = temp_path("input.txt");
+        fs::write(&path, "5")?;
+
+        let run = run_cli(
+            "factorial.bf",
+            &["--input".as_ref(), path.as_os_str()],
+            Program::from_str(FACTORIAL)?,
+        );
+        fs::remove_file(&path)?;
+
+        assert_eq!(run?, ("120 ".to_string(), None));
+
+        Ok(())
+    }
+
+    #[test]
+    fn output_writes_to_file() -> Result<(), Box<dyn std::error::Error>> {
+        let (output, _) = run_cli("hello_world.bf", &[], Program::from_str(HELLO_WORLD)?)?;
+
+        assert_eq!(output, "Hello, World!\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn runs_far_apart_sparse_programs() -> Result<(), Box<dyn std::error::Error>> {
+        // densifying this would need a grid with trillions of cells
+        let mut program = Program::from_str("7.@")?;
+        program.set(
+            &Position {
+                x: 1 << 20,
+                y: 1 << 20,
+            },
+            '#',
+        );
+        program.set(
+            &Position {
+                x: -(1 << 20),
+                y: -(1 << 20),
+            },
+            '#',
+        );
+        let (output, _) = run_cli("sparse.bf", &[], program)?;
+
+        assert_eq!(output, "7 ");
+
+        Ok(())
+    }
+
+    #[test]
+    fn trace_file_is_complete_after_quitting() -> Result<(), Box<dyn std::error::Error>> {
+        let path = temp_path("quit-trace.txt");
+
+        // enough steps that the trace doesn't fit in the file's write buffer
+        let source = format!("{}3q", "0".repeat(500));
+        let run = run_cli(
+            "quit.b98",
+            &["--trace-file".as_ref(), path.as_os_str()],
+            Program::from_str(&source)?,
+        );
+        let trace = fs::read_to_string(&path);
+        fs::remove_file(&path)?;
+
+        assert_eq!(run?.1, Some(3));
+        let trace = trace?;
+        assert_eq!(trace.lines().count(), 502);
+        assert!(trace.ends_with('\n'));
+
+        Ok(())
+    }
+
+    #[test]
+    fn program_from_stdin() -> Result<(), Box<dyn std::error::Error>> {
+        let cli = Cli::parse_from(["fungoid", "run", "-", "--input", "/dev/null"]);
+        let (file, options) = match cli.command {
+            Commands::Run { file, options, .. } => (file, options),
+            _ => panic!("expected the run command"),
+        };
+
+        let program = load_program(&file, false, &options, HELLO_WORLD.as_bytes())?;
+        let input = [];
+        let mut execution = ExecutionState::new(program, false, input.as_slice(), Vec::new());
+        execution.run()?;
+
+        assert_eq!(String::from_utf8(execution.output)?, "Hello, World!\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn program_from_stdin_requires_input() {
+        let cli = Cli::parse_from(["fungoid", "run", "-"]);
+        let (file, options) = match cli.command {
+            Commands::Run { file, options, .. } => (file, options),
+            _ => panic!("expected the run command"),
+        };
+
+        assert!(load_program(&file, false, &options, HELLO_WORLD.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn dialect_from_extension_unless_overridden() {
+        let dialect = |args: &[&str]| match Cli::parse_from(args).command {
+            Commands::Run { file, options, .. } => options.dialect(Some(&file)),
+            _ => panic!("expected the run command"),
+        };
+
+        assert_eq!(dialect(&["fungoid", "run", "prog.bf"]), Dialect::Befunge93);
+        assert_eq!(dialect(&["fungoid", "run", "prog.b98"]), Dialect::Funge98);
+        assert_eq!(dialect(&["fungoid", "run", "prog.txt"]), Dialect::Befunge93);
+        assert_eq!(
+            dialect(&["fungoid", "run", "prog.b98", "--dialect", "befunge93"]),
+            Dialect::Befunge93
+        );
+        assert_eq!(
+            dialect(&["fungoid", "run", "prog.bf", "--dialect", "funge98"]),
+            Dialect::Funge98
+        );
+    }
+
+    #[test]
+    fn quiet_profile_is_just_the_instruction_count() -> Result<(), Box<dyn std::error::Error>> {
+        let mut output = Vec::new();
+        let mut execution = ExecutionState::builder(HELLO_WORLD.parse::<Program>()?)
+            .profile(true)
+            .build(std::io::empty(), &mut output);
+        execution.run()?;
+
+        let mut report = Vec::new();
+        write_profile(&mut report, &execution, Duration::from_secs(1), true)?;
+        assert_eq!(String::from_utf8(report)?, "107\n");
+
+        let mut report = Vec::new();
+        write_profile(&mut report, &execution, Duration::from_secs(1), false)?;
+        let report = String::from_utf8(report)?;
+        assert!(
+            report.starts_with("Executed 107 instructions in 1s (107 instructions/second)\n"),
+            "{}",
+            report
+        );
+        assert!(report.contains("Instructions executed:\n"), "{}", report);
+
+        Ok(())
+    }
+
+    #[test]
+    fn memory_report_counts_filled_cells() -> Result<(), Box<dyn std::error::Error>> {
+        // densifying stores the spaces too, but they aren't part of the playfield's contents
+        let program = "123v\n\n   @".parse::<Program>()?.to_dense();
+        assert!(program.stored_cell_count() > program.cell_count());
+        let mut execution = ExecutionState::new(program, false, std::io::empty(), Vec::new());
+        execution.run()?;
+
+        let mut report = Vec::new();
+        write_memory_report(&mut report, &execution)?;
+        let report = String::from_utf8(report)?;
+        assert!(
+            report.starts_with("Playfield holds 5 cells, peak stack depth was 3 "),
+            "{}",
+            report
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn quiet_requires_profile() {
+        assert!(Cli::try_parse_from(["fungoid", "run", "prog.bf", "--quiet"]).is_err());
+        assert!(Cli::try_parse_from(["fungoid", "run", "prog.bf", "--profile", "--quiet"]).is_ok());
+    }
+
+    #[test]
+    fn examples_list_takes_no_arguments() {
+        let cli = Cli::try_parse_from(["fungoid", "examples", "list"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Commands::Examples(ExamplesArgs {
+                command: ExamplesCommands::List
+            })
+        ));
+    }
+
+    #[test]
+    fn example_list_includes_descriptions() {
+        let list = example_list(None);
+
+        assert!(
+            list.lines().any(|line| line.starts_with("quine ")
+                && line.ends_with(" — a program that prints its own source")),
+            "{}",
+            list
+        );
+        assert_eq!(list.lines().count(), 7);
+    }
+
+    #[test]
+    fn example_search_matches_names_and_descriptions() {
+        assert_eq!(
+            example_list(Some("PRIME")).lines().collect::<Vec<_>>(),
+            vec!["eratosthenes — prints the primes below 80, using the sieve of Eratosthenes"]
+        );
+        assert_eq!(
+            example_list(Some("hello")).lines().count(),
+            1,
+            "{}",
+            example_list(Some("hello"))
+        );
+        assert_eq!(example_list(Some("random")).lines().count(), 2);
+        assert_eq!(example_list(Some("nothing like this")), "");
+    }
+
+    #[test]
+    fn all_examples_run_without_errors() {
+        let cli = Cli::parse_from(["fungoid", "examples", "run", "--all", "--max-steps", "5000"]);
+        if let Commands::Examples(ExamplesArgs {
+            command:
+                ExamplesCommands::Run {
+                    example: None,
+                    all: true,
+                    max_steps,
+                    options,
+                },
+        }) = cli.command
+        {
+            assert_eq!(max_steps, Some(5000));
+            let results = smoke_test_examples(5000, &options);
+
+            assert_eq!(results.len(), 7);
+            for (name, instructions, result) in results {
+                assert!(result.is_ok(), "{}: {:?}", name, result);
+                assert!(instructions <= 5000, "{}", name);
+            }
+        } else {
+            panic!("expected examples run --all");
+        }
+    }
+
+    #[test]
+    fn examples_run_needs_an_example_or_all() {
+        assert!(Cli::try_parse_from(["fungoid", "examples", "run", "--ips", "3"]).is_err());
+        assert!(Cli::try_parse_from(["fungoid", "examples", "run", "quine", "--all"]).is_err());
+    }
+
+    #[test]
+    fn info_reports_version_dialects_and_features() {
+        let info = info();
+
+        assert!(
+            info.starts_with(&format!("fungoid {}\n", env!("CARGO_PKG_VERSION"))),
+            "{}",
+            info
+        );
+        assert!(
+            info.contains("Dialects: befunge93 (default), funge98\n"),
+            "{}",
+            info
+        );
+        assert_eq!(info.contains("serde"), cfg!(feature = "serde"), "{}", info);
+    }
+
+    #[test]
+    fn every_samples_the_output() -> Result<(), Box<dyn std::error::Error>> {
+        let counting = "\"0\">:,1+:\"9\"`!v\n   ^          _@";
+        let mut output = Vec::new();
+        let mut execution = ExecutionState::new(
+            counting.parse::<Program>()?,
+            false,
+            std::io::empty(),
+            Sampled::new(&mut output, 2),
+        );
+        execution.run()?;
+
+        assert_eq!(String::from_utf8(output)?, "13579");
+
+        // numbers are sampled whole, with their separator
+        let mut output = Vec::new();
+        let mut execution = ExecutionState::new(
+            "1.23*.45*.@".parse::<Program>()?,
+            false,
+            std::io::empty(),
+            Sampled::new(&mut output, 2),
+        );
+        execution.run()?;
+
+        assert_eq!(String::from_utf8(output)?, "6 ");
+
+        Ok(())
+    }
+
+    #[test]
+    fn sampling_counts_writes() -> Result<(), Box<dyn std::error::Error>> {
+        let mut output = Vec::new();
+        let mut sampled = Sampled::new(&mut output, 3);
+        sampled.write_all(b"ab")?;
+        sampled.write_all(b"cdefg")?;
+        sampled.write_all(b"h")?;
+        sampled.write_all(b"i")?;
+
+        assert_eq!(output, b"h");
+
+        let cli = Cli::parse_from(["fungoid", "run", "prog.bf", "--every", "3"]);
+        if let Commands::Run { options, .. } = cli.command {
+            assert_eq!(options.every, Some(3));
+        } else {
+            panic!("expected the run command");
+        }
+        assert!(Cli::try_parse_from(["fungoid", "run", "prog.bf", "--every", "0"]).is_err());
+
+        Ok(())
+    }
 }
@@ -1,15 +1,151 @@
-use std::{collections::HashMap, ffi::OsString, fs::File, io, io::Read, str::FromStr};
+use std::{
+    collections::{BTreeMap, HashMap},
+    convert::TryInto,
+    ffi::OsString,
+    fmt,
+    fmt::{Display, Formatter},
+    fs,
+    fs::File,
+    io,
+    io::{BufRead, BufReader, Read},
+    mem::size_of,
+    path::Path,
+    str::FromStr,
+};
 
-use itertools::{Itertools, MinMaxResult};
+use itertools::{Either, Itertools};
+
+use crate::execution::PointerDirection;
+
+/// The error returned by `Program::with_bounds` for a field with no cells,
+/// or with too many cells to allocate.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct InvalidBounds {
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Display for InvalidBounds {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "A {}x{} program can't be allocated; the width and height must be positive and their product must fit in memory",
+            self.width, self.height
+        )
+    }
+}
+
+impl std::error::Error for InvalidBounds {}
+
+/// The number of cells in the box between two corners, or `None` if it doesn't fit in a `usize`.
+fn box_area(upper_left: Position, lower_right: Position) -> Option<usize> {
+    let span = |low: isize, high: isize| high.abs_diff(low).checked_add(1);
+    span(upper_left.x, lower_right.x)?.checked_mul(span(upper_left.y, lower_right.y)?)
+}
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Position {
     pub x: isize,
     pub y: isize,
 }
 
-#[derive(Debug, Clone)]
-pub struct Program(HashMap<Position, char>);
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(into = "ProgramData", from = "ProgramData")
+)]
+pub struct Program {
+    grid: Grid,
+    comment: Option<String>,
+    /// The width and height of a field (from the origin) that the extent always covers,
+    /// set by `ensure_bounds`.
+    bounds: Option<(isize, isize)>,
+}
+
+/// The serialized form of a `Program`.
+/// Cells are stored as a list, since positions can't be used as JSON object keys.
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct ProgramData {
+    cells: Vec<(Position, char)>,
+    /// The width, height, and wrapping of a dense grid.
+    dense: Option<(isize, isize, bool)>,
+    comment: Option<String>,
+    #[serde(default)]
+    bounds: Option<(isize, isize)>,
+}
+
+#[cfg(feature = "serde")]
+impl From<Program> for ProgramData {
+    fn from(program: Program) -> Self {
+        ProgramData {
+            cells: program.cells().sorted().collect(),
+            dense: match program.grid {
+                Grid::Sparse(_) => None,
+                Grid::Dense {
+                    width,
+                    height,
+                    wraps,
+                    ..
+                } => Some((width, height, wraps)),
+            },
+            comment: program.comment,
+            bounds: program.bounds,
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<ProgramData> for Program {
+    fn from(data: ProgramData) -> Self {
+        let mut program = match data.dense {
+            Some((width, height, wraps)) => Program {
+                grid: Grid::Dense {
+                    width,
+                    height,
+                    cells: vec![' '; (width * height) as usize],
+                    wraps,
+                },
+                comment: None,
+                bounds: None,
+            },
+            None => Program::new(),
+        };
+        for (pos, c) in data.cells {
+            program.set(&pos, c);
+        }
+        program.comment = data.comment;
+        program.bounds = data.bounds;
+
+        program
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Grid {
+    Sparse(HashMap<Position, char>),
+    /// A fixed-size field stored row by row.
+    /// If `wraps`, positions outside the field wrap around it, like the Befunge-93 torus;
+    /// otherwise they are blank, and setting one converts the grid to `Sparse`.
+    Dense {
+        width: isize,
+        height: isize,
+        cells: Vec<char>,
+        wraps: bool,
+    },
+}
+
+/// The file extension for programs stored in the compact binary format.
+pub const BINARY_EXTENSION: &str = "bfz";
+
+const BINARY_MAGIC: &[u8; 4] = b"BFZ\x01";
+const BINARY_HEADER_LEN: usize = BINARY_MAGIC.len() + 8;
+const BINARY_CELL_LEN: usize = 8 + 8 + 4;
+
+/// The largest bounding box, in cells, that `is_compact` always considers worth densifying.
+pub const MAX_DENSE_AREA: usize = 1 << 24;
 
 impl Position {
     pub(crate) fn shifted(&self, x: isize, y: isize) -> Self {
@@ -18,27 +154,382 @@ impl Position {
             y: self.y + y,
         }
     }
+
+    /// The number of horizontal and vertical moves it takes to get from here to `other`,
+    /// saturating at `usize::MAX` for positions at opposite corners of the coordinate space.
+    pub fn manhattan_distance(&self, other: &Position) -> usize {
+        self.x
+            .abs_diff(other.x)
+            .saturating_add(self.y.abs_diff(other.y))
+    }
+
+    /// The adjacent position in direction `dir`.
+    pub fn neighbor(&self, dir: PointerDirection) -> Position {
+        let (dx, dy) = dir.delta();
+        self.shifted(dx, dy)
+    }
 }
 
 impl Program {
     fn new() -> Self {
-        Program(HashMap::new())
+        Program {
+            grid: Grid::Sparse(HashMap::new()),
+            comment: None,
+            bounds: None,
+        }
+    }
+
+    /// Creates a blank program backed by a fixed-size `width` by `height` field,
+    /// with O(1) access to every cell.
+    /// Getting or setting a cell outside the field wraps around to the opposite edge.
+    /// Fails if the field would be empty or too large to allocate.
+    pub fn with_bounds(width: usize, height: usize) -> Result<Self, InvalidBounds> {
+        let area = width
+            .checked_mul(height)
+            .filter(|&area| area > 0)
+            .filter(|area| {
+                area.checked_mul(size_of::<char>())
+                    .is_some_and(|bytes| bytes <= isize::MAX as usize)
+            })
+            .ok_or(InvalidBounds { width, height })?;
+
+        Ok(Program {
+            grid: Grid::Dense {
+                width: width as isize,
+                height: height as isize,
+                cells: vec![' '; area],
+                wraps: true,
+            },
+            comment: None,
+            bounds: None,
+        })
+    }
+
+    /// A copy of the program backed by a flat grid covering its extent (from the origin),
+    /// so that `get` and `set` don't need to hash positions.
+    /// The copy behaves exactly like the original: setting a cell outside the grid
+    /// makes it fall back to sparse storage.
+    /// Programs with cells at negative coordinates, or that aren't `is_compact`,
+    /// are copied as they are.
+    pub fn to_dense(&self) -> Program {
+        let lower_right = match self.extent() {
+            Some((upper_left, lower_right))
+                if matches!(self.grid, Grid::Sparse(_))
+                    && upper_left.x >= 0
+                    && upper_left.y >= 0
+                    && self.is_compact() =>
+            {
+                lower_right
+            }
+            _ => return self.clone(),
+        };
+
+        let size = lower_right
+            .x
+            .checked_add(1)
+            .zip(lower_right.y.checked_add(1))
+            .and_then(|(width, height)| Some((width, height, width.checked_mul(height)?)));
+        let (width, height, area) = match size {
+            Some(size) => size,
+            None => return self.clone(),
+        };
+        let mut cells = vec![' '; area as usize];
+        for (pos, c) in self.cells() {
+            cells[dense_index(&pos, width, height)] = c;
+        }
+
+        Program {
+            grid: Grid::Dense {
+                width,
+                height,
+                cells,
+                wraps: false,
+            },
+            comment: self.comment.clone(),
+            bounds: self.bounds,
+        }
     }
 
     pub fn get(&self, pos: &Position) -> char {
-        *self.0.get(pos).unwrap_or(&' ')
+        match &self.grid {
+            Grid::Sparse(cells) => *cells.get(pos).unwrap_or(&' '),
+            Grid::Dense {
+                width,
+                height,
+                cells,
+                wraps,
+            } => {
+                if *wraps || in_field(pos, *width, *height) {
+                    cells[dense_index(pos, *width, *height)]
+                } else {
+                    ' '
+                }
+            }
+        }
     }
 
     pub fn set(&mut self, pos: &Position, c: char) {
-        self.0.insert(*pos, c);
+        match &mut self.grid {
+            Grid::Sparse(cells) => {
+                cells.insert(*pos, c);
+            }
+            Grid::Dense {
+                width,
+                height,
+                cells,
+                wraps,
+            } => {
+                if *wraps || in_field(pos, *width, *height) {
+                    cells[dense_index(pos, *width, *height)] = c;
+                } else {
+                    self.grid = Grid::Sparse(self.cells().collect());
+                    self.set(pos, c);
+                }
+            }
+        }
+    }
+
+    /// The character stored at `pos`, or `None` if nothing is stored there.
+    /// Unlike `get`, this distinguishes a stored space from an empty cell,
+    /// which matters for the program's extent.
+    pub(crate) fn stored(&self, pos: &Position) -> Option<char> {
+        match &self.grid {
+            Grid::Sparse(cells) => cells.get(pos).copied(),
+            Grid::Dense {
+                width,
+                height,
+                cells,
+                wraps,
+            } => (*wraps || in_field(pos, *width, *height))
+                .then(|| cells[dense_index(pos, *width, *height)]),
+        }
+    }
+
+    /// Forgets the character stored at `pos`, undoing a `set` of a cell that was empty.
+    /// A dense grid stores every cell in its field, so those are set to a space instead.
+    pub(crate) fn remove(&mut self, pos: &Position) {
+        match &mut self.grid {
+            Grid::Sparse(cells) => {
+                cells.remove(pos);
+            }
+            Grid::Dense { .. } => {
+                if self.stored(pos).is_some() {
+                    self.set(pos, ' ');
+                }
+            }
+        }
+    }
+
+    /// Iterates over every stored cell, in no particular order.
+    pub fn cells(&self) -> impl Iterator<Item = (Position, char)> + '_ {
+        match &self.grid {
+            Grid::Sparse(cells) => Either::Left(cells.iter().map(|(p, c)| (*p, *c))),
+            Grid::Dense { width, cells, .. } => {
+                Either::Right(cells.iter().enumerate().map(move |(i, c)| {
+                    (
+                        Position {
+                            x: i as isize % width,
+                            y: i as isize / width,
+                        },
+                        *c,
+                    )
+                }))
+            }
+        }
+    }
+
+    /// The number of cells held in memory, including blank cells.
+    pub fn stored_cell_count(&self) -> usize {
+        match &self.grid {
+            Grid::Sparse(cells) => cells.len(),
+            Grid::Dense { cells, .. } => cells.len(),
+        }
+    }
+
+    /// The number of cells holding something other than a space.
+    pub fn cell_count(&self) -> usize {
+        self.cells().filter(|(_, c)| *c != ' ').count()
+    }
+
+    /// The number of cells in the bounding box given by `extent`, or zero for an empty program.
+    /// Saturates at `usize::MAX` if the cells are too far apart to count.
+    pub fn area(&self) -> usize {
+        self.extent().map_or(0, |(upper_left, lower_right)| {
+            box_area(upper_left, lower_right).unwrap_or(usize::MAX)
+        })
+    }
+
+    /// Whether a dense copy of the program (see `to_dense`) would be a reasonable size:
+    /// either the rectangle from the origin that covers its extent has at most `MAX_DENSE_AREA` cells,
+    /// or at least a quarter of that rectangle is filled in.
+    /// A few cells far apart from each other, or far from the origin, are cheaper to keep sparse.
+    pub fn is_compact(&self) -> bool {
+        let (upper_left, lower_right) = match self.extent() {
+            Some((upper_left, lower_right)) => (
+                Position {
+                    x: upper_left.x.min(0),
+                    y: upper_left.y.min(0),
+                },
+                lower_right,
+            ),
+            None => return true,
+        };
+
+        match box_area(upper_left, lower_right) {
+            Some(area) => area <= MAX_DENSE_AREA || self.cell_count().saturating_mul(4) >= area,
+            None => false,
+        }
+    }
+
+    /// A rough estimate of the memory used by the cells, ignoring any allocator overhead.
+    pub fn estimated_bytes(&self) -> usize {
+        match &self.grid {
+            Grid::Sparse(cells) => cells.capacity() * size_of::<(Position, char)>(),
+            Grid::Dense { cells, .. } => cells.capacity() * size_of::<char>(),
+        }
     }
 
     pub fn from_file(path: &OsString) -> Result<Self, io::Error> {
         let mut f = File::open(path)?;
-        let mut contents = String::new();
-        f.read_to_string(&mut contents)?;
 
-        Program::from_str(&contents)
+        if Path::new(path)
+            .extension()
+            .is_some_and(|e| e == BINARY_EXTENSION)
+        {
+            let mut contents = Vec::new();
+            f.read_to_end(&mut contents)?;
+
+            return Program::from_bytes(&contents);
+        }
+
+        Program::from_reader(f)
+    }
+
+    /// Parses a program from a reader line by line, without reading it all into memory first.
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self, io::Error> {
+        let mut program = Program::new();
+
+        for (y, line) in BufReader::new(reader).lines().enumerate() {
+            program.set_row(y as isize, &line?);
+        }
+
+        Ok(program)
+    }
+
+    /// Assembles a program from a directory of files named `row_0000`, `row_0001`, ...,
+    /// where each file holds one row of the program.
+    pub fn from_dir(path: &OsString) -> Result<Self, io::Error> {
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+
+        let mut rows = BTreeMap::new();
+        for entry in fs::read_dir(path)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let y = name
+                .to_str()
+                .and_then(|n| n.strip_prefix("row_"))
+                .and_then(|n| n.parse::<usize>().ok())
+                .ok_or_else(|| invalid(format!("Not a row file: {:?}", name)))?;
+            rows.insert(y, entry.path());
+        }
+
+        let mut program = Program::new();
+        for (expected, (y, row_path)) in rows.iter().enumerate() {
+            if *y != expected {
+                return Err(invalid(format!("Missing row file for row {}", expected)));
+            }
+
+            let contents = fs::read_to_string(row_path)?;
+            program.set_row(*y as isize, contents.trim_end_matches(&['\n', '\r'][..]));
+        }
+
+        Ok(program)
+    }
+
+    /// Parses a program like `from_str`, but stops at the first line equal to `sentinel`
+    /// (ignoring trailing whitespace).
+    /// Everything after the sentinel line is kept as the program's comment instead of becoming
+    /// part of the playfield.
+    pub fn from_str_with_sentinel(s: &str, sentinel: &str) -> Self {
+        let mut program = Program::new();
+
+        let mut lines = s.lines();
+        for (y, line) in lines.by_ref().enumerate() {
+            if line.trim_end() == sentinel {
+                program.comment = Some(lines.join("\n"));
+                break;
+            }
+            program.set_row(y as isize, line);
+        }
+
+        program
+    }
+
+    /// The text that followed the sentinel line, if the program was parsed with one.
+    pub fn comment(&self) -> Option<&str> {
+        self.comment.as_deref()
+    }
+
+    fn set_row(&mut self, y: isize, line: &str) {
+        for (x, c) in line.chars().enumerate() {
+            self.set(&Position { x: x as isize, y }, c);
+        }
+    }
+
+    /// Serializes the program into the compact binary format:
+    /// a magic number and a little-endian `u64` cell count,
+    /// followed by one `(x: i64, y: i64, char: u32)` little-endian triple per cell.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes =
+            Vec::with_capacity(BINARY_HEADER_LEN + self.stored_cell_count() * BINARY_CELL_LEN);
+        bytes.extend_from_slice(BINARY_MAGIC);
+        bytes.extend_from_slice(&(self.stored_cell_count() as u64).to_le_bytes());
+
+        for (pos, c) in self.cells().sorted() {
+            bytes.extend_from_slice(&(pos.x as i64).to_le_bytes());
+            bytes.extend_from_slice(&(pos.y as i64).to_le_bytes());
+            bytes.extend_from_slice(&u32::from(c).to_le_bytes());
+        }
+
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, io::Error> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+
+        if bytes.len() < BINARY_HEADER_LEN || &bytes[..BINARY_MAGIC.len()] != BINARY_MAGIC {
+            return Err(invalid("Not a binary Befunge program"));
+        }
+
+        let count = u64::from_le_bytes(
+            bytes[BINARY_MAGIC.len()..BINARY_HEADER_LEN]
+                .try_into()
+                .unwrap(),
+        );
+        let cells = &bytes[BINARY_HEADER_LEN..];
+        if cells.len() as u64 != count.saturating_mul(BINARY_CELL_LEN as u64) {
+            return Err(invalid(
+                "Binary program length does not match its cell count",
+            ));
+        }
+
+        let mut program = Program::new();
+        for cell in cells.chunks_exact(BINARY_CELL_LEN) {
+            let x = i64::from_le_bytes(cell[0..8].try_into().unwrap());
+            let y = i64::from_le_bytes(cell[8..16].try_into().unwrap());
+            let c = char::from_u32(u32::from_le_bytes(cell[16..20].try_into().unwrap()))
+                .ok_or_else(|| invalid("Binary program contains an invalid character"))?;
+
+            program.set(
+                &Position {
+                    x: x as isize,
+                    y: y as isize,
+                },
+                c,
+            );
+        }
+
+        Ok(program)
     }
 
     pub fn view(
@@ -54,13 +545,166 @@ impl Program {
             })
     }
 
+    /// A copy of the program translated so that the upper-left corner of its extent is at (0, 0),
+    /// along with the offset that was added to every position
+    /// (add it to the pointer's position, too, to keep running the translated program).
+    pub fn normalized(&self) -> (Program, Position) {
+        let offset = match self.extent() {
+            Some((upper_left, _)) if matches!(self.grid, Grid::Sparse(_)) => Position {
+                x: -upper_left.x,
+                y: -upper_left.y,
+            },
+            _ => return (self.clone(), Position { x: 0, y: 0 }),
+        };
+
+        let mut program = Program::new();
+        program.comment = self.comment.clone();
+        // bounds always start at the origin, so grow them to cover the translated field
+        program.bounds = self
+            .bounds
+            .map(|(width, height)| (width + offset.x, height + offset.y));
+        for (pos, c) in self.cells() {
+            program.set(&pos.shifted(offset.x, offset.y), c);
+        }
+
+        (program, offset)
+    }
+
+    /// Whether the program contains a `p` instruction, and so might modify itself.
+    /// This is a conservative check: a `p` inside a string still counts.
+    pub fn is_self_modifying(&self) -> bool {
+        self.cells().any(|(_, c)| c == 'p')
+    }
+
+    /// Compares this program against `original`, returning `(position, old, new)`
+    /// for every cell that differs, in reading order (row by row).
+    /// Unstored cells count as spaces, so this covers the union of both extents.
+    pub fn diff(&self, original: &Program) -> Vec<(Position, char, char)> {
+        self.cells()
+            .chain(original.cells())
+            .map(|(pos, _)| pos)
+            .unique()
+            .sorted_by_key(|pos| (pos.y, pos.x))
+            .filter_map(|pos| {
+                let (old, new) = (original.get(&pos), self.get(&pos));
+                (old != new).then_some((pos, old, new))
+            })
+            .collect()
+    }
+
+    /// Iterates over the cells in row `y`, across the program's extent.
+    pub fn iter_row(&self, y: isize) -> impl Iterator<Item = (Position, char)> + '_ {
+        self.extent()
+            .into_iter()
+            .flat_map(move |(ul, lr)| self.view(&Position { x: ul.x, y }, &Position { x: lr.x, y }))
+    }
+
+    /// Iterates over the cells in column `x`, across the program's extent.
+    pub fn iter_column(&self, x: isize) -> impl Iterator<Item = (Position, char)> + '_ {
+        self.extent()
+            .into_iter()
+            .flat_map(move |(ul, lr)| self.view(&Position { x, y: ul.y }, &Position { x, y: lr.y }))
+    }
+
     pub fn extent(&self) -> Option<(Position, Position)> {
-        match self.0.keys().minmax() {
-            MinMaxResult::NoElements => None,
-            MinMaxResult::OneElement(e) => Some((*e, *e)),
-            MinMaxResult::MinMax(ul, lr) => Some((*ul, *lr)),
+        let cells = self.cell_extent();
+        let bounds = self.bounds.map(|(width, height)| {
+            (
+                Position { x: 0, y: 0 },
+                Position {
+                    x: width - 1,
+                    y: height - 1,
+                },
+            )
+        });
+
+        match (cells, bounds) {
+            (Some((ul, lr)), Some((bounds_ul, bounds_lr))) => Some((
+                Position {
+                    x: ul.x.min(bounds_ul.x),
+                    y: ul.y.min(bounds_ul.y),
+                },
+                Position {
+                    x: lr.x.max(bounds_lr.x),
+                    y: lr.y.max(bounds_lr.y),
+                },
+            )),
+            (cells, bounds) => cells.or(bounds),
+        }
+    }
+
+    /// The bounding box of the stored cells.
+    fn cell_extent(&self) -> Option<(Position, Position)> {
+        if let Grid::Dense { width, height, .. } = self.grid {
+            return Some((
+                Position { x: 0, y: 0 },
+                Position {
+                    x: width - 1,
+                    y: height - 1,
+                },
+            ));
+        }
+
+        let (min_x, max_x) = self.cells().map(|(p, _)| p.x).minmax().into_option()?;
+        let (min_y, max_y) = self.cells().map(|(p, _)| p.y).minmax().into_option()?;
+
+        Some((
+            Position { x: min_x, y: min_y },
+            Position { x: max_x, y: max_y },
+        ))
+    }
+
+    /// Makes the extent cover at least a `width` by `height` field from the origin,
+    /// as if it were filled with spaces, without storing any cells.
+    /// This fixes the field that `Wrapping::Extent` wraps around,
+    /// for programs that expect the 80x25 Befunge-93 playfield.
+    pub fn ensure_bounds(&mut self, width: isize, height: isize) {
+        assert!(width > 0 && height > 0, "Program bounds must be non-empty");
+
+        self.bounds = Some(match self.bounds {
+            Some((w, h)) => (w.max(width), h.max(height)),
+            None => (width, height),
+        });
+    }
+}
+
+/// Finds the first fenced code block (delimited by ```` ``` ```` or `~~~`)
+/// whose info string starts with `befunge` in a Markdown document, and returns its contents.
+pub fn extract_fenced_program(text: &str) -> Option<String> {
+    let mut lines = text.lines();
+
+    while let Some(line) = lines.next() {
+        let line = line.trim_start();
+        let fence_char = match line.chars().next() {
+            Some(c @ ('`' | '~')) => c,
+            _ => continue,
+        };
+        let fence_len = line.chars().take_while(|c| *c == fence_char).count();
+        if fence_len < 3 || !line[fence_len..].trim().starts_with("befunge") {
+            continue;
         }
+
+        let block = lines
+            .by_ref()
+            .take_while(|l| {
+                let l = l.trim();
+                l.chars().take_while(|c| *c == fence_char).count() < fence_len
+                    || l.chars().any(|c| c != fence_char)
+            })
+            .join("\n");
+
+        return Some(block);
     }
+
+    None
+}
+
+fn in_field(pos: &Position, width: isize, height: isize) -> bool {
+    (0..width).contains(&pos.x) && (0..height).contains(&pos.y)
+}
+
+fn dense_index(pos: &Position, width: isize, height: isize) -> usize {
+    (pos.y.rem_euclid(height) * width + pos.x.rem_euclid(width)) as usize
 }
 
 impl FromStr for Program {
@@ -70,28 +714,51 @@ impl FromStr for Program {
         let mut program = Program::new();
 
         for (y, line) in s.lines().enumerate() {
-            for (x, c) in line.chars().enumerate() {
-                program.set(
-                    &Position {
-                        x: x as isize,
-                        y: y as isize,
-                    },
-                    c,
-                );
-            }
+            program.set_row(y as isize, line);
         }
 
         Ok(program)
     }
 }
 
+/// Renders the program as text that `from_str` parses back into the same cells,
+/// with trailing spaces trimmed from each row.
+/// Cells at negative coordinates can't be represented, so they are left out
+/// (use `normalized` to move them into view first).
+impl Display for Program {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let lower_right = match self.extent() {
+            Some((_, lower_right)) if lower_right.x >= 0 && lower_right.y >= 0 => lower_right,
+            _ => return Ok(()),
+        };
+
+        let text = self
+            .view(&Position { x: 0, y: 0 }, &lower_right)
+            .group_by(|(p, _)| p.y)
+            .into_iter()
+            .map(|(_, row)| {
+                row.map(|(_, c)| c)
+                    .collect::<String>()
+                    .trim_end_matches(' ')
+                    .to_string()
+            })
+            .join("\n");
+
+        write!(f, "{}", text.trim_end_matches('\n'))
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use std::str::FromStr;
+    use std::{ffi::OsString, fs, str::FromStr};
 
     use itertools::Itertools;
 
-    use crate::program::{Position, Program};
+    use crate::{
+        examples::{ERATOSTHENES, EXAMPLES, HELLO_WORLD, QUINE},
+        execution::PointerDirection,
+        program::{extract_fenced_program, InvalidBounds, Position, Program, BINARY_EXTENSION},
+    };
 
     type GenericResult = Result<(), Box<dyn std::error::Error>>;
 
@@ -169,6 +836,18 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_extent_is_the_bounding_box() -> GenericResult {
+        let program = Program::from_str("abc\nd")?;
+
+        assert_eq!(
+            program.extent().unwrap(),
+            (Position { x: 0, y: 0 }, Position { x: 2, y: 1 })
+        );
+
+        Ok(())
+    }
+
     #[test]
     fn test_view() -> GenericResult {
         let mut program = Program::new();
@@ -245,4 +924,587 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_bytes_round_trip_sparse_program() -> GenericResult {
+        let mut program = Program::new();
+        program.set(&Position { x: -5, y: -3 }, '>');
+        program.set(&Position { x: 0, y: 0 }, '@');
+        program.set(&Position { x: 1000, y: -2000 }, 'é');
+
+        assert_eq!(Program::from_bytes(&program.to_bytes())?, program);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_bytes_rejects_garbage() {
+        assert!(Program::from_bytes(b"12\n34").is_err());
+    }
+
+    #[test]
+    fn test_from_file_dispatches_on_binary_extension() -> GenericResult {
+        let mut program = Program::new();
+        program.set(&Position { x: -1, y: 0 }, '@');
+
+        let path = std::env::temp_dir().join(format!(
+            "fungoid-{}-test.{}",
+            std::process::id(),
+            BINARY_EXTENSION
+        ));
+        fs::write(&path, program.to_bytes())?;
+        let loaded = Program::from_file(&OsString::from(&path));
+        fs::remove_file(&path)?;
+
+        assert_eq!(loaded?, program);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bounded_program_get_and_set() -> GenericResult {
+        let mut program = Program::with_bounds(3, 2)?;
+
+        assert_eq!(program.get(&Position { x: 2, y: 1 }), ' ');
+
+        program.set(&Position { x: 2, y: 1 }, '@');
+        assert_eq!(program.get(&Position { x: 2, y: 1 }), '@');
+        assert_eq!(
+            program.extent().unwrap(),
+            (Position { x: 0, y: 0 }, Position { x: 2, y: 1 })
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bounded_program_wraps_out_of_bounds_access() -> GenericResult {
+        let mut program = Program::with_bounds(3, 2)?;
+
+        program.set(&Position { x: 3, y: 0 }, 'a');
+        assert_eq!(program.get(&Position { x: 0, y: 0 }), 'a');
+
+        program.set(&Position { x: -1, y: -1 }, 'b');
+        assert_eq!(program.get(&Position { x: 2, y: 1 }), 'b');
+
+        assert_eq!(program.cells().count(), 6);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bounded_program_rejects_empty_and_oversized_bounds() {
+        for (width, height) in [(0, 2), (3, 0), (usize::MAX, 2), (1 << 62, 1)] {
+            assert_eq!(
+                Program::with_bounds(width, height),
+                Err(InvalidBounds { width, height })
+            );
+        }
+    }
+
+    #[test]
+    fn test_from_dir_assembles_rows() -> GenericResult {
+        let dir = std::env::temp_dir().join(format!("fungoid-{}-rows", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        fs::write(dir.join("row_0000"), "v\n")?;
+        fs::write(dir.join("row_0001"), ">@")?;
+        let program = Program::from_dir(&OsString::from(&dir));
+
+        fs::write(dir.join("row_0003"), "@")?;
+        let gap = Program::from_dir(&OsString::from(&dir));
+        fs::remove_dir_all(&dir)?;
+
+        assert_eq!(program?, Program::from_str("v\n>@")?);
+        assert!(gap.is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_iter_row_and_column() -> GenericResult {
+        let program = Program::from_str("abc\nd\nefg")?;
+
+        assert_eq!(
+            program.iter_row(1).collect_vec(),
+            vec![
+                (Position { x: 0, y: 1 }, 'd'),
+                (Position { x: 1, y: 1 }, ' '),
+                (Position { x: 2, y: 1 }, ' '),
+            ]
+        );
+        assert_eq!(
+            program.iter_column(2).collect_vec(),
+            vec![
+                (Position { x: 2, y: 0 }, 'c'),
+                (Position { x: 2, y: 1 }, ' '),
+                (Position { x: 2, y: 2 }, 'g'),
+            ]
+        );
+        assert_eq!(Program::new().iter_row(0).count(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_str_with_sentinel() -> GenericResult {
+        let program =
+            Program::from_str_with_sentinel("v\n>@\n---\nThis program\ndoes nothing.", "---");
+
+        assert_eq!(program.extent().unwrap().1, Position { x: 1, y: 1 });
+        assert_eq!(program.get(&Position { x: 0, y: 3 }), ' ');
+        assert_eq!(program.comment(), Some("This program\ndoes nothing."));
+
+        assert!(Program::from_str("v\n>@\n---")?.comment().is_none());
+        assert_eq!(
+            Program::from_str("v\n>@\n---")?.get(&Position { x: 0, y: 2 }),
+            '-'
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_fenced_program() {
+        let markdown = "# Example\n\n```rust\nfn main() {}\n```\n\n~~~befunge\n>1.@\n  ```\n~~~\n";
+
+        assert_eq!(
+            extract_fenced_program(markdown),
+            Some(">1.@\n  ```".to_string())
+        );
+        assert_eq!(extract_fenced_program("```\n>1.@\n```"), None);
+    }
+
+    #[test]
+    fn test_is_self_modifying() -> GenericResult {
+        assert!(Program::from_str(ERATOSTHENES)?.is_self_modifying());
+        // the quine reads itself with g, but never writes
+        assert!(!Program::from_str(QUINE)?.is_self_modifying());
+        assert!(!Program::from_str(HELLO_WORLD)?.is_self_modifying());
+
+        Ok(())
+    }
+
+    fn non_blank_cells(program: &Program) -> Vec<(Position, char)> {
+        program
+            .cells()
+            .filter(|(_, c)| *c != ' ')
+            .sorted()
+            .collect()
+    }
+
+    #[test]
+    fn display_round_trips_examples() -> GenericResult {
+        for (name, example) in EXAMPLES.iter() {
+            let program = Program::from_str(example.source)?;
+            let round_tripped = Program::from_str(&program.to_string())?;
+
+            assert_eq!(
+                non_blank_cells(&round_tripped),
+                non_blank_cells(&program),
+                "{}",
+                name
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn display_trims_trailing_spaces() -> GenericResult {
+        let program = Program::from_str("  \n 1 2  \n\n3\n   \n")?;
+
+        assert_eq!(program.to_string(), "\n 1 2\n\n3");
+
+        Ok(())
+    }
+
+    #[test]
+    fn display_empty_program() -> GenericResult {
+        assert_eq!(Program::from_str("")?.to_string(), "");
+
+        Ok(())
+    }
+
+    #[test]
+    fn display_self_modified_program() -> GenericResult {
+        let mut program = Program::from_str("@")?;
+        program.set(&Position { x: 2, y: 1 }, 'p');
+
+        assert_eq!(program.to_string(), "@\n  p");
+
+        Ok(())
+    }
+
+    #[test]
+    fn normalized_moves_negative_cells_into_view() -> GenericResult {
+        let mut program = Program::from_str("ab")?;
+        program.set(&Position { x: -2, y: -1 }, 'x');
+
+        let (normalized, offset) = program.normalized();
+
+        assert_eq!(offset, Position { x: 2, y: 1 });
+        assert_eq!(normalized.get(&Position { x: 0, y: 0 }), 'x');
+        assert_eq!(normalized.to_string(), "x\n  ab");
+
+        // the bounds set by ensure_bounds move along with the cells
+        program.ensure_bounds(80, 25);
+        let (normalized, offset) = program.normalized();
+
+        assert_eq!(offset, Position { x: 2, y: 1 });
+        assert_eq!(normalized.bounds, Some((82, 26)));
+        assert_eq!(
+            normalized.extent(),
+            Some((Position { x: 0, y: 0 }, Position { x: 81, y: 25 }))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn normalized_leaves_programs_at_the_origin_alone() -> GenericResult {
+        let program = Program::from_str(HELLO_WORLD)?;
+
+        let (normalized, offset) = program.normalized();
+
+        assert_eq!(offset, Position { x: 0, y: 0 });
+        assert_eq!(normalized, program);
+
+        Ok(())
+    }
+
+    #[test]
+    fn from_reader_matches_from_str() -> GenericResult {
+        for source in [HELLO_WORLD, ERATOSTHENES, QUINE, "a\r\nb\n\nc"] {
+            let program = Program::from_reader(source.as_bytes())?;
+
+            assert_eq!(program, Program::from_str(source)?);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn dense_program_matches_sparse() -> GenericResult {
+        let sparse = Program::from_str(ERATOSTHENES)?;
+        let dense = sparse.to_dense();
+
+        assert_eq!(dense.extent(), sparse.extent());
+        assert_eq!(non_blank_cells(&dense), non_blank_cells(&sparse));
+        assert_eq!(dense.to_string(), sparse.to_string());
+
+        Ok(())
+    }
+
+    #[test]
+    fn dense_program_falls_back_to_sparse() -> GenericResult {
+        let mut program = Program::from_str("ab\ncd")?.to_dense();
+
+        assert_eq!(program.get(&Position { x: 2, y: 0 }), ' ');
+        assert_eq!(program.get(&Position { x: -1, y: 0 }), ' ');
+
+        program.set(&Position { x: 10, y: -5 }, 'x');
+
+        assert_eq!(program.get(&Position { x: 10, y: -5 }), 'x');
+        assert_eq!(program.get(&Position { x: 1, y: 1 }), 'd');
+        assert_eq!(
+            program.extent(),
+            Some((Position { x: 0, y: -5 }, Position { x: 10, y: 1 }))
+        );
+
+        Ok(())
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() -> GenericResult {
+        let mut sparse = Program::from_str_with_sentinel("12p\n@\n__END__\nnotes", "__END__");
+        sparse.set(&Position { x: -3, y: 7 }, 'x');
+        let dense = Program::from_str(ERATOSTHENES)?.to_dense();
+        let bounded = Program::with_bounds(3, 2)?;
+
+        for program in [sparse, dense, bounded] {
+            let json = serde_json::to_string(&program)?;
+            assert_eq!(serde_json::from_str::<Program>(&json)?, program);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn cell_count_ignores_spaces() {
+        let mut program = Program::new();
+        assert_eq!(program.cell_count(), 0);
+        assert_eq!(program.area(), 0);
+
+        program.set(&Position { x: -2, y: 1 }, 'a');
+        program.set(&Position { x: 5, y: 3 }, 'b');
+        program.set(&Position { x: 0, y: 0 }, ' ');
+        program.set(&Position { x: 1, y: 2 }, 'c');
+
+        assert_eq!(program.cell_count(), 3);
+        assert_eq!(program.area(), 8 * 4);
+    }
+
+    #[test]
+    fn area_saturates_for_far_apart_cells() {
+        let mut program = Program::new();
+        program.set(
+            &Position {
+                x: isize::MIN,
+                y: 0,
+            },
+            'a',
+        );
+        program.set(
+            &Position {
+                x: isize::MAX,
+                y: 0,
+            },
+            'b',
+        );
+        assert_eq!(program.area(), usize::MAX);
+
+        program.set(
+            &Position {
+                x: 0,
+                y: isize::MAX,
+            },
+            'c',
+        );
+        assert_eq!(program.area(), usize::MAX);
+        assert!(!program.is_compact());
+    }
+
+    #[test]
+    fn dense_cell_count_matches_sparse() -> GenericResult {
+        let sparse = Program::from_str(ERATOSTHENES)?;
+        let dense = sparse.to_dense();
+
+        assert_eq!(dense.cell_count(), sparse.cell_count());
+        assert_eq!(dense.area(), sparse.area());
+
+        Ok(())
+    }
+
+    #[test]
+    fn ensure_bounds_extends_the_extent() -> GenericResult {
+        let mut program = Program::from_str("ab\nc")?;
+        let cells = program.stored_cell_count();
+
+        program.ensure_bounds(80, 25);
+
+        assert_eq!(
+            program.extent(),
+            Some((Position { x: 0, y: 0 }, Position { x: 79, y: 24 }))
+        );
+        assert_eq!(program.area(), 80 * 25);
+        assert_eq!(program.stored_cell_count(), cells);
+        assert_eq!(program.get(&Position { x: 50, y: 20 }), ' ');
+        assert_eq!(program.to_string(), "ab\nc");
+
+        // cells outside the bounds still extend the extent
+        program.set(&Position { x: 90, y: -1 }, 'x');
+        assert_eq!(
+            program.extent(),
+            Some((Position { x: 0, y: -1 }, Position { x: 90, y: 24 }))
+        );
+
+        // bounds only grow
+        program.ensure_bounds(10, 30);
+        assert_eq!(program.extent().unwrap().1, Position { x: 90, y: 29 });
+
+        Ok(())
+    }
+
+    #[test]
+    fn ensure_bounds_on_an_empty_program() {
+        let mut program = Program::new();
+        program.ensure_bounds(3, 2);
+
+        assert_eq!(
+            program.extent(),
+            Some((Position { x: 0, y: 0 }, Position { x: 2, y: 1 }))
+        );
+        assert_eq!(program.to_dense().stored_cell_count(), 6);
+    }
+
+    #[test]
+    fn diff_lists_changed_cells() -> GenericResult {
+        let original = Program::from_str(HELLO_WORLD)?;
+        let mut program = original.clone();
+
+        assert_eq!(program.diff(&original), vec![]);
+
+        program.set(&Position { x: 3, y: 1 }, 'x');
+        program.set(&Position { x: 0, y: 0 }, '<');
+        // setting a cell to what it already holds isn't a change
+        program.set(
+            &Position { x: 1, y: 0 },
+            original.get(&Position { x: 1, y: 0 }),
+        );
+        // outside the original's extent
+        program.set(&Position { x: -5, y: 40 }, '7');
+
+        assert_eq!(
+            program.diff(&original),
+            vec![
+                (Position { x: 0, y: 0 }, '6', '<'),
+                (Position { x: 3, y: 1 }, ' ', 'x'),
+                (Position { x: -5, y: 40 }, ' ', '7'),
+            ]
+        );
+
+        // and in reverse
+        assert_eq!(
+            original.diff(&program)[2],
+            (Position { x: -5, y: 40 }, '7', ' ')
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn diff_against_dense_program() -> GenericResult {
+        let original = Program::from_str("12\n34")?;
+        let mut program = original.to_dense();
+        program.set(&Position { x: 1, y: 1 }, ' ');
+
+        assert_eq!(
+            program.diff(&original),
+            vec![(Position { x: 1, y: 1 }, '4', ' ')]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn manhattan_distance() {
+        let origin = Position { x: 0, y: 0 };
+
+        assert_eq!(origin.manhattan_distance(&origin), 0);
+        assert_eq!(origin.manhattan_distance(&Position { x: 3, y: 4 }), 7);
+        assert_eq!(origin.manhattan_distance(&Position { x: -3, y: 4 }), 7);
+        assert_eq!(
+            Position { x: -2, y: -5 }.manhattan_distance(&Position { x: 1, y: -1 }),
+            7
+        );
+        assert_eq!(
+            Position { x: 1, y: -1 }.manhattan_distance(&Position { x: -2, y: -5 }),
+            7
+        );
+
+        let corner = Position {
+            x: isize::MIN,
+            y: isize::MIN,
+        };
+        assert_eq!(
+            corner.manhattan_distance(&Position {
+                x: isize::MAX,
+                y: 0
+            }),
+            usize::MAX
+        );
+        assert_eq!(
+            corner.manhattan_distance(&Position {
+                x: isize::MAX,
+                y: isize::MAX
+            }),
+            usize::MAX
+        );
+    }
+
+    #[test]
+    fn neighbor() {
+        let position = Position { x: 0, y: -1 };
+
+        assert_eq!(
+            position.neighbor(PointerDirection::Up),
+            Position { x: 0, y: -2 }
+        );
+        assert_eq!(
+            position.neighbor(PointerDirection::Down),
+            Position { x: 0, y: 0 }
+        );
+        assert_eq!(
+            position.neighbor(PointerDirection::Left),
+            Position { x: -1, y: -1 }
+        );
+        assert_eq!(
+            position.neighbor(PointerDirection::Right),
+            Position { x: 1, y: -1 }
+        );
+        assert_eq!(
+            position
+                .neighbor(PointerDirection::Left)
+                .manhattan_distance(&position),
+            1
+        );
+    }
+
+    #[test]
+    fn far_apart_cells_are_not_compact() -> GenericResult {
+        assert!(Program::new().is_compact());
+        assert!(Program::from_str(ERATOSTHENES)?.is_compact());
+
+        let mut program = Program::from_str(">v\n^<")?;
+        program.set(
+            &Position {
+                x: 1 << 40,
+                y: 1 << 40,
+            },
+            '@',
+        );
+        assert!(!program.is_compact());
+
+        let mut program = Program::new();
+        program.set(&Position { x: 0, y: 0 }, 'a');
+        program.set(
+            &Position {
+                x: isize::MAX,
+                y: 0,
+            },
+            'b',
+        );
+        assert!(!program.is_compact());
+
+        // far from the origin, even on its own
+        let mut program = Program::new();
+        program.set(
+            &Position {
+                x: 1 << 40,
+                y: 1 << 40,
+            },
+            '@',
+        );
+        assert!(!program.is_compact());
+
+        Ok(())
+    }
+
+    #[test]
+    fn to_dense_keeps_far_apart_programs_sparse() -> GenericResult {
+        let mut program = Program::from_str("ab")?;
+        program.set(
+            &Position {
+                x: isize::MAX,
+                y: 3,
+            },
+            'c',
+        );
+        let dense = program.to_dense();
+        assert_eq!(dense.stored_cell_count(), 3);
+        assert_eq!(dense, program);
+
+        let mut program = Program::new();
+        program.set(
+            &Position {
+                x: 1 << 40,
+                y: 1 << 40,
+            },
+            '@',
+        );
+        assert_eq!(program.to_dense().stored_cell_count(), 1);
+
+        Ok(())
+    }
 }
@@ -0,0 +1,133 @@
+//! WebAssembly bindings for `Interpreter`, enabled by the `wasm` feature.
+//!
+//! Build with `wasm-pack build --features wasm`, then drive the interpreter from JavaScript:
+//!
+//! ```js
+//! import { Status, WasmInterpreter } from "fungoid";
+//!
+//! const interpreter = new WasmInterpreter("&2*.@");
+//! interpreter.feedInput("21\n");
+//! while (interpreter.stepMany(1000) === Status.Running) {}
+//! console.log(interpreter.output()); // "42 "
+//! console.log(interpreter.pointer()); // Float64Array [4, 0]
+//! ```
+
+use wasm_bindgen::prelude::*;
+
+use crate::interpreter::{Interpreter, Status};
+
+/// An `Interpreter` that can be used from JavaScript.
+/// Errors are thrown as strings describing them.
+#[wasm_bindgen]
+pub struct WasmInterpreter {
+    interpreter: Interpreter,
+}
+
+#[wasm_bindgen]
+impl WasmInterpreter {
+    /// Creates an interpreter for the program in `source`.
+    #[wasm_bindgen(constructor)]
+    pub fn new(source: &str) -> WasmInterpreter {
+        WasmInterpreter {
+            interpreter: Interpreter::from_source(source),
+        }
+    }
+
+    /// Queues up text for the program to read.
+    #[wasm_bindgen(js_name = feedInput)]
+    pub fn feed_input(&mut self, input: &str) {
+        self.interpreter.feed_input(input.as_bytes());
+    }
+
+    /// Executes one step, unless the program has terminated or is waiting for input.
+    pub fn step(&mut self) -> Result<Status, String> {
+        self.interpreter.step().map_err(|e| e.to_string())
+    }
+
+    /// Executes up to `n` steps, stopping early if the program terminates or waits for input.
+    #[wasm_bindgen(js_name = stepMany)]
+    pub fn step_many(&mut self, n: u32) -> Result<Status, String> {
+        self.interpreter
+            .step_many(u64::from(n))
+            .map_err(|e| e.to_string())
+    }
+
+    pub fn status(&self) -> Status {
+        self.interpreter.status()
+    }
+
+    /// Everything the program has written so far, with invalid UTF-8 replaced.
+    pub fn output(&self) -> String {
+        String::from_utf8_lossy(self.interpreter.output()).into_owned()
+    }
+
+    /// Removes and returns everything the program has written since the last call.
+    #[wasm_bindgen(js_name = takeOutput)]
+    pub fn take_output(&mut self) -> String {
+        String::from_utf8_lossy(&self.interpreter.take_output()).into_owned()
+    }
+
+    /// The instruction pointer's position, as `[x, y]`.
+    pub fn pointer(&self) -> Vec<f64> {
+        let position = self.interpreter.execution().pointer.position;
+        vec![position.x as f64, position.y as f64]
+    }
+
+    /// The stack, from bottom to top.
+    pub fn stack(&self) -> Vec<f64> {
+        self.interpreter
+            .execution()
+            .stack
+            .items()
+            .into_iter()
+            .map(|item| item as f64)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{examples::HELLO_WORLD, interpreter::Status, wasm::WasmInterpreter};
+
+    type GenericResult = Result<(), Box<dyn std::error::Error>>;
+
+    #[test]
+    fn hello_world() -> GenericResult {
+        let mut interpreter = WasmInterpreter::new(HELLO_WORLD);
+
+        assert_eq!(interpreter.step_many(1_000)?, Status::Terminated);
+        assert_eq!(interpreter.output(), "Hello, World!\n");
+        assert_eq!(interpreter.take_output(), "Hello, World!\n");
+        assert_eq!(interpreter.output(), "");
+
+        Ok(())
+    }
+
+    #[test]
+    fn pointer_stack_and_input() -> GenericResult {
+        let mut interpreter = WasmInterpreter::new("&2*.@");
+
+        assert_eq!(interpreter.step()?, Status::WaitingForInput);
+        interpreter.feed_input("21\n");
+        assert_eq!(interpreter.step()?, Status::Running);
+        assert_eq!(interpreter.step()?, Status::Running);
+        assert_eq!(interpreter.pointer(), vec![2.0, 0.0]);
+        assert_eq!(interpreter.stack(), vec![21.0, 2.0]);
+
+        assert_eq!(interpreter.step_many(10)?, Status::Terminated);
+        assert_eq!(interpreter.output(), "42 ");
+        assert_eq!(interpreter.pointer(), vec![4.0, 0.0]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn errors_are_strings() {
+        let mut interpreter = WasmInterpreter::new("z");
+
+        assert_eq!(
+            interpreter.step(),
+            Err("Unrecognized instruction at (x=0, y=0): 'z'".to_string())
+        );
+    }
+}